@@ -0,0 +1,45 @@
+// Measures how many instructions a second the interpreter runs, with and
+// without the decode cache:
+//
+//     cargo run --release --example throughput [STEPS]
+extern crate chip8_cpu;
+
+use chip8_cpu::cpu::Cpu;
+use chip8_cpu::gamedb::GameDb;
+
+use std::env;
+use std::time::Instant;
+
+// Register arithmetic, skips and jumps, the bread and butter of game loops
+const ROM: [u8; 14] = [
+    0x60, 0x00, // V0 = 0
+    0x70, 0x01, // V0 += 1
+    0x81, 0x04, // V1 += V0
+    0x82, 0x13, // V2 ^= V1
+    0x30, 0x00, // skip if V0 == 0
+    0x12, 0x02, // jump 0x202
+    0x12, 0x00, // jump 0x200
+];
+
+// Instructions a second
+fn measure(decode_cache: bool, steps: u32) -> f64 {
+    let mut processor = Cpu::new();
+    processor.set_game_db(GameDb::default());
+    processor.load_rom(ROM.to_vec());
+    processor.set_decode_cache(decode_cache);
+
+    let start = Instant::now();
+    for _ in 0..steps {
+        processor.step([false; 16]);
+    }
+    steps as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let steps = env::args().nth(1).and_then(|steps| steps.parse().ok()).unwrap_or(50_000_000);
+
+    let uncached = measure(false, steps);
+    let cached = measure(true, steps);
+    println!("decoding every step: {:7.1} million instructions a second", uncached / 1e6);
+    println!("with the cache:      {:7.1} million instructions a second ({:.2}x)", cached / 1e6, cached / uncached);
+}
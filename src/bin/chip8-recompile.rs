@@ -0,0 +1,43 @@
+extern crate chip8_cpu;
+
+use chip8_cpu::cpu::Cpu;
+use chip8_cpu::gamedb::GameDb;
+use chip8_cpu::platform::Quirks;
+use chip8_cpu::recompiler;
+
+use std::env;
+use std::fs;
+
+// chip8-recompile ROM [--quirks NAME] [-o FILE]
+//
+// Writes the ROM out as a Rust program for the chip8_cpu runtime, to stdout
+// unless -o says where. Quirks default to what the emulator would pick.
+fn main() {
+    let mut args = env::args().skip(1);
+    let (mut filename, mut quirks, mut output) = (None, None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let preset = args.next().expect("--quirks needs chip8, schip, xochip or legacy");
+                quirks = Some(Quirks::preset(&preset).unwrap_or_else(|e| panic!("{}", e)));
+            },
+            "-o" => output = Some(args.next().expect("-o needs a file")),
+            _ => filename = Some(arg)
+        }
+    }
+    let filename = filename.expect("Usage: chip8-recompile ROM [--quirks NAME] [-o FILE]");
+    let rom = fs::read(&filename).unwrap_or_else(|e| panic!("Could not read {}: {}", filename, e));
+
+    let quirks = quirks.unwrap_or_else(|| {
+        let mut cpu = Cpu::new();
+        cpu.set_game_db(GameDb::builtin());
+        cpu.load_rom(rom.clone());
+        cpu.quirks()
+    });
+
+    let source = recompiler::recompile(&rom, quirks);
+    match output {
+        Some(path) => fs::write(&path, source).unwrap_or_else(|e| panic!("Could not write {}: {}", path, e)),
+        None => print!("{}", source)
+    }
+}
@@ -0,0 +1,320 @@
+use gif;
+
+use std::collections::HashMap;
+
+use gamedb::GameInfo;
+use octo;
+use palette::Palette;
+use platform::{Platform, Quirks};
+
+// An Octo cartridge: a GIF whose pixels carry a program and the settings it
+// was written for. Every byte of the payload is spread over the low two bits
+// of the palette indices of four pixels, high bits first, and the payload is
+// a 4 byte length followed by that many bytes of JSON:
+//
+//     {"program": "<Octo source>", "options": {"tickrate": 20, ...}}
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    // the settings, as a game database entry
+    pub game: GameInfo,
+}
+
+// GIFs start with GIF87a or GIF89a
+pub fn is_cartridge(file: &[u8]) -> bool {
+    file.starts_with(b"GIF8")
+}
+
+impl Cartridge {
+    pub fn decode(file: &[u8]) -> Result<Cartridge, String> {
+        let payload = payload(file)?;
+        let json = Json::parse(&String::from_utf8_lossy(&payload))?;
+        let program = json.get("program").and_then(Json::as_str).ok_or("The cartridge has no program")?;
+        let rom = octo::assemble(program).map_err(|e| format!("Could not assemble the program: {}", e))?;
+        let game = match json.get("options") {
+            Some(options) => game_info(options)?,
+            None => GameInfo::default()
+        };
+        Ok(Cartridge { rom, game })
+    }
+}
+
+fn payload(file: &[u8]) -> Result<Vec<u8>, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(file).map_err(|e| e.to_string())?;
+
+    let mut pixels = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+        pixels.extend_from_slice(&frame.buffer);
+    }
+
+    let bytes: Vec<u8> = pixels.chunks(4)
+        .filter(|group| group.len() == 4)
+        .map(|group| group.iter().fold(0, |byte, &index| byte << 2 | (index & 3)))
+        .collect();
+    if bytes.len() < 4 {
+        return Err(String::from("The image is too small to be a cartridge"));
+    }
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    bytes.get(4..4 + size).map(<[u8]>::to_vec).ok_or_else(|| String::from("The image doesn't hold a cartridge"))
+}
+
+// Octo's options, which name their quirks after what the original CHIP-8
+// did differently
+fn game_info(options: &Json) -> Result<GameInfo, String> {
+    let flag = |name: &str| options.get(name).and_then(Json::as_bool).unwrap_or(false);
+    let color = |name: &str| options.get(name).and_then(Json::as_str);
+
+    // the most memory each platform has, which Octo picks the target by
+    let platform = match options.get("maxSize").and_then(Json::as_f64) {
+        Some(size) if size <= 3232.0 => Some(Platform::Chip8),
+        Some(size) if size <= 3584.0 => Some(Platform::Schip),
+        Some(_) => Some(Platform::XoChip),
+        None => None
+    };
+
+    let palette = match (color("backgroundColor"), color("fillColor")) {
+        (Some(background), Some(fill)) => {
+            let mut colors = vec![background, fill];
+            colors.extend(color("fillColor2"));
+            colors.extend(color("blendColor"));
+            Some(Palette::parse(&colors.join(","))?)
+        },
+        _ => None
+    };
+
+    let mut game = GameInfo::default();
+    game.platform = platform;
+    game.instructions_per_frame = options.get("tickrate").and_then(Json::as_f64)
+        .filter(|&rate| rate >= 1.0).map(|rate| rate as u32);
+    game.palette = palette;
+    game.set_quirks(Quirks {
+        shift_vy: !flag("shiftQuirks"),
+        increment_i: !flag("loadStoreQuirks"),
+        jump_vx: flag("jumpQuirks"),
+        vf_reset: flag("logicQuirks"),
+        clip: flag("clipQuirks"),
+    });
+    Ok(game)
+}
+
+// Just the JSON cartridges use
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(HashMap<String, Json>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut chars = text.chars().peekable();
+        let json = parse_value(&mut chars)?;
+        skip_space(&mut chars);
+        match chars.next() {
+            Some(c) => Err(format!("Unexpected {} after the JSON", c)),
+            None => Ok(json)
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.get(key),
+            _ => None
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            _ => None
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+}
+
+type Chars<'a> = ::std::iter::Peekable<::std::str::Chars<'a>>;
+
+fn skip_space(chars: &mut Chars) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_value(chars: &mut Chars) -> Result<Json, String> {
+    skip_space(chars);
+    match chars.peek().cloned() {
+        Some('{') => {
+            chars.next();
+            let mut members = HashMap::new();
+            skip_space(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(members));
+            }
+            loop {
+                skip_space(chars);
+                if chars.next() != Some('"') {
+                    return Err(String::from("Expected a key"));
+                }
+                let key = parse_string(chars)?;
+                skip_space(chars);
+                if chars.next() != Some(':') {
+                    return Err(format!("Expected : after {}", key));
+                }
+                members.insert(key, parse_value(chars)?);
+                skip_space(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some('}') => return Ok(Json::Object(members)),
+                    _ => return Err(String::from("Expected , or }"))
+                }
+            }
+        },
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_space(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars)?);
+                skip_space(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some(']') => return Ok(Json::Array(items)),
+                    _ => return Err(String::from("Expected , or ]"))
+                }
+            }
+        },
+        Some('"') => {
+            chars.next();
+            parse_string(chars).map(Json::String)
+        },
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            number.parse().map(Json::Number).map_err(|_| format!("{} is not a number", number))
+        },
+        Some(_) => {
+            let mut word = String::new();
+            while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                word.extend(chars.next());
+            }
+            match word.as_str() {
+                "true" => Ok(Json::Bool(true)),
+                "false" => Ok(Json::Bool(false)),
+                "null" => Ok(Json::Null),
+                _ => Err(format!("Unexpected {}", word))
+            }
+        },
+        None => Err(String::from("The JSON ends too soon"))
+    }
+}
+
+// The rest of a string whose opening quote has been read
+fn parse_string(chars: &mut Chars) -> Result<String, String> {
+    let mut units = Vec::new();
+    loop {
+        let c = chars.next().ok_or("Unterminated string")?;
+        match c {
+            '"' => return Ok(String::from_utf16_lossy(&units)),
+            '\\' => {
+                let escaped = match chars.next().ok_or("Unterminated string")? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        let unit = u16::from_str_radix(&hex, 16).map_err(|_| format!("Bad escape \\u{}", hex))?;
+                        // surrogate pairs come as two escapes
+                        units.push(unit);
+                        continue;
+                    },
+                    other => other
+                };
+                units.extend(escaped.encode_utf16(&mut [0; 2]).iter());
+            },
+            _ => units.extend(c.encode_utf16(&mut [0; 2]).iter())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gif;
+
+    use cartridge::{is_cartridge, Cartridge, Json};
+    use platform::Platform;
+
+    // A cartridge the way Octo builds them: four palette entries per color,
+    // the data in the low two bits of each pixel
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = payload.iter()
+            .flat_map(|&byte| (0..4).rev().map(move |pair| 4 | (byte >> (pair * 2)) & 3))
+            .collect();
+        let (width, height) = (32, pixels.len().div_ceil(32) as u16);
+        pixels.resize(32 * height as usize, 4);
+
+        let palette: Vec<u8> = (0..8).flat_map(|i| if i < 4 { [0, 0, 0] } else { [255, 255, 255] }).collect();
+        let mut file = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut file, width, height, &palette).unwrap();
+            encoder.write_frame(&gif::Frame::from_indexed_pixels(width, height, &pixels, None)).unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn test_decode() {
+        let json = r##"{"program": ": main\n  clear\n  loop again", "options": {"tickrate": 15,
+                       "shiftQuirks": true, "clipQuirks": true, "maxSize": 3584,
+                       "backgroundColor": "#000000", "fillColor": "#FFCC00", "unknown": [1, null, "é"]}}"##;
+        let file = cartridge(json);
+        assert!(is_cartridge(&file));
+        let cartridge = Cartridge::decode(&file).unwrap();
+        assert_eq!(cartridge.rom, vec![0x12, 0x02, 0x00, 0xE0, 0x12, 0x04]);
+        assert_eq!(cartridge.game.platform, Some(Platform::Schip));
+        assert_eq!(cartridge.game.instructions_per_frame, Some(15));
+        let quirks = cartridge.game.quirks().unwrap();
+        assert!(!quirks.shift_vy && quirks.clip && quirks.increment_i);
+        assert_eq!(cartridge.game.palette.unwrap().color(1), (0xFF, 0xCC, 0x00));
+    }
+
+    #[test]
+    fn test_json() {
+        let json = Json::parse(r#" {"a": [true, -1.5e1, "x\"😀"]} "#).unwrap();
+        assert_eq!(json.get("a"), Some(&Json::Array(vec![
+            Json::Bool(true), Json::Number(-15.0), Json::String(String::from("x\"\u{1F600}"))
+        ])));
+        assert!(Json::parse("{\"a\": 1,}").is_err());
+    }
+}
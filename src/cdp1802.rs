@@ -0,0 +1,387 @@
+// The RCA CDP1802, the COSMAC VIP's processor. Everything outside it, memory
+// included, is reached through a Bus.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+
+    // INP 1-7 and OUT 1-7
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+    fn output(&mut self, _port: u8, _value: u8) {}
+
+    // The EF1-EF4 input lines, n from 1 to 4
+    fn flag(&self, _n: u8) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cdp1802 {
+    // the scratchpad registers R0-RF
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    // which register is the program counter, and which the data pointer
+    pub p: u8,
+    pub x: u8,
+    // X and P saved by an interrupt or MARK
+    pub t: u8,
+    // interrupts enabled
+    pub ie: bool,
+    pub q: bool,
+    // stopped by IDL until a DMA or interrupt
+    pub idle: bool,
+}
+
+impl Cdp1802 {
+    // The state after a reset: P, X and R0 cleared, interrupts on
+    pub fn new() -> Cdp1802 {
+        Cdp1802 { ie: true, ..Cdp1802::default() }
+    }
+
+    // Runs one instruction and returns the machine cycles (8 clocks each) it
+    // took. An idle processor does nothing for a cycle.
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        if self.idle {
+            return 1;
+        }
+        let opcode = self.fetch(bus);
+        let (i, n) = (opcode >> 4, opcode & 0xF);
+        let nr = n as usize;
+        let xr = self.x as usize;
+
+        match i {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = bus.read(self.r[nr]),
+            0x1 => self.r[nr] = self.r[nr].wrapping_add(1),
+            0x2 => self.r[nr] = self.r[nr].wrapping_sub(1),
+            0x3 => {
+                let taken = self.condition(n & 7, bus) != (n & 8 != 0);
+                // 38 (SKP) is the "never" branch, it skips its byte
+                self.short_branch(taken, bus);
+            },
+            0x4 => {
+                self.d = bus.read(self.r[nr]);
+                self.r[nr] = self.r[nr].wrapping_add(1);
+            },
+            0x5 => bus.write(self.r[nr], self.d),
+            0x6 if n == 0 => self.r[xr] = self.r[xr].wrapping_add(1),
+            0x6 if n < 8 => {
+                let value = bus.read(self.r[xr]);
+                bus.output(n, value);
+                self.r[xr] = self.r[xr].wrapping_add(1);
+            },
+            // 68 is left for the CDP1804's extra instructions
+            0x6 if n == 8 => (),
+            0x6 => {
+                self.d = bus.input(n - 8);
+                bus.write(self.r[xr], self.d);
+            },
+            0x7 => self.op_7(n, bus),
+            0x8 => self.d = self.r[nr] as u8,
+            0x9 => self.d = (self.r[nr] >> 8) as u8,
+            0xA => self.r[nr] = self.r[nr] & 0xFF00 | self.d as u16,
+            0xB => self.r[nr] = self.r[nr] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.long_branch_or_skip(n, bus);
+                return 3;
+            },
+            0xD => self.p = n,
+            0xE => self.x = n,
+            _ => self.op_f(n, bus)
+        }
+        2
+    }
+
+    // Responds to an interrupt request, if interrupts are on. Takes a cycle.
+    pub fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+        self.t = self.x << 4 | self.p;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    // A DMA out cycle: the byte at R0 goes to the device and R0 moves on
+    pub fn dma_out<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let p = self.p as usize;
+        let value = bus.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    // Conditions of the branches, the negated ones have bit 3 set:
+    // always, Q, D == 0, DF, EF1-EF4
+    fn condition<B: Bus>(&self, n: u8, bus: &B) -> bool {
+        match n {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            _ => bus.flag(n - 3)
+        }
+    }
+
+    fn short_branch<B: Bus>(&mut self, taken: bool, bus: &mut B) {
+        let p = self.p as usize;
+        if taken {
+            let low = bus.read(self.r[p]);
+            self.r[p] = self.r[p] & 0xFF00 | low as u16;
+        } else {
+            self.r[p] = self.r[p].wrapping_add(1);
+        }
+    }
+
+    // Cx: long branches on the same conditions as the short ones, except
+    // C4 (NOP), C5-C7 and CC-CF, which skip two bytes instead
+    fn long_branch_or_skip<B: Bus>(&mut self, n: u8, bus: &mut B) {
+        let p = self.p as usize;
+        let skip = match n {
+            0x4 => return,
+            0x5 => Some(!self.q),
+            0x6 => Some(self.d != 0),
+            0x7 => Some(!self.df),
+            0x8 => Some(true),
+            0xC => Some(self.ie),
+            0xD => Some(self.q),
+            0xE => Some(self.d == 0),
+            0xF => Some(self.df),
+            _ => None
+        };
+        match skip {
+            Some(skip) => if skip {
+                self.r[p] = self.r[p].wrapping_add(2);
+            },
+            None => {
+                let taken = self.condition(n & 3, bus) != (n & 8 != 0);
+                if taken {
+                    let high = bus.read(self.r[p]) as u16;
+                    let low = bus.read(self.r[p].wrapping_add(1)) as u16;
+                    self.r[p] = high << 8 | low;
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            }
+        }
+    }
+
+    // The operand of the arithmetic at 7x and Fx: M(R(X)), or the immediate
+    // byte for the ones with bit 3 set
+    fn operand<B: Bus>(&mut self, n: u8, bus: &mut B) -> u8 {
+        if n & 8 != 0 {
+            self.fetch(bus)
+        } else {
+            bus.read(self.r[self.x as usize])
+        }
+    }
+
+    // D = a + b + carry
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    fn op_7<B: Bus>(&mut self, n: u8, bus: &mut B) {
+        let xr = self.x as usize;
+        match n {
+            // RET and DIS
+            0x0 | 0x1 => {
+                let value = bus.read(self.r[xr]);
+                self.r[xr] = self.r[xr].wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0;
+            },
+            0x2 => {
+                self.d = bus.read(self.r[xr]);
+                self.r[xr] = self.r[xr].wrapping_add(1);
+            },
+            0x3 => {
+                bus.write(self.r[xr], self.d);
+                self.r[xr] = self.r[xr].wrapping_sub(1);
+            },
+            // SHRC and SHLC
+            0x6 => {
+                let carry = self.df;
+                self.df = self.d & 1 != 0;
+                self.d = self.d >> 1 | (carry as u8) << 7;
+            },
+            0xE => {
+                let carry = self.df;
+                self.df = self.d & 0x80 != 0;
+                self.d = self.d << 1 | carry as u8;
+            },
+            0x8 => bus.write(self.r[xr], self.t),
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            },
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // ADC, SDB, SMB and their immediate forms. A borrow is DF clear.
+            _ => {
+                let operand = self.operand(n, bus);
+                let (d, carry) = (self.d, self.df);
+                match n & 7 {
+                    0x4 => self.add(operand, d, carry),
+                    0x5 => self.add(operand, !d, carry),
+                    _ => self.add(d, !operand, carry)
+                }
+            }
+        }
+    }
+
+    fn op_f<B: Bus>(&mut self, n: u8, bus: &mut B) {
+        match n {
+            0x6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            },
+            0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            },
+            // LDX and LDI
+            0x0 | 0x8 => self.d = self.operand(n, bus),
+            _ => {
+                let operand = self.operand(n, bus);
+                let d = self.d;
+                match n & 7 {
+                    0x1 => self.d |= operand,
+                    0x2 => self.d &= operand,
+                    0x3 => self.d ^= operand,
+                    0x4 => self.add(operand, d, false),
+                    // SD and SM, a - b being a + !b + 1
+                    0x5 => self.add(operand, !d, true),
+                    _ => self.add(d, !operand, true)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cdp1802::{Bus, Cdp1802};
+
+    struct Ram {
+        memory: [u8; 0x100],
+        ef: [bool; 4],
+        out: Vec<(u8, u8)>,
+    }
+
+    impl Bus for Ram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize & 0xFF]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize & 0xFF] = value;
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            0x40 + port
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.out.push((port, value));
+        }
+
+        fn flag(&self, n: u8) -> bool {
+            self.ef[n as usize - 1]
+        }
+    }
+
+    // Runs a program from 0 until it idles
+    fn run(program: &[u8]) -> (Cdp1802, Ram) {
+        let mut ram = Ram { memory: [0; 0x100], ef: [false, false, true, false], out: Vec::new() };
+        ram.memory[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::new();
+        let mut cycles = 0;
+        while !cpu.idle {
+            cycles += cpu.step(&mut ram);
+            assert!(cycles < 10000, "the program never stopped");
+        }
+        (cpu, ram)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // D = 0xF0 + 0x20 into R5, branching over a SEQ on the carry, then
+        // 0x10 - 0x30 and 0x30 >> 1
+        let (cpu, _) = run(&[0xF8, 0xF0, 0xFC, 0x20, 0xA5, 0x33, 0x08, 0x7B,
+                             0xF8, 0x10, 0xFF, 0x30, 0xB5, 0xF8, 0x30, 0xF6, 0xA6, 0x00]);
+        assert_eq!(cpu.r[5], 0xE010);
+        assert!(!cpu.q);
+        assert_eq!(cpu.r[6], 0x0018);
+        assert!(!cpu.df);
+
+        // a 16 bit add with the carry: 0x01FF + 0x0001
+        let (cpu, _) = run(&[0xF8, 0xFF, 0xFC, 0x01, 0xA7, 0xF8, 0x01, 0x7C, 0x00, 0xB7, 0x00]);
+        assert_eq!(cpu.r[7], 0x0200);
+    }
+
+    #[test]
+    fn test_branches() {
+        // count R3 down from 3, then long branch over a SEQ when EF3 is set
+        let (cpu, _) = run(&[0xF8, 0x03, 0xA3, 0x23, 0x83, 0x3A, 0x03, 0x36, 0x0A,
+                             0x7B, 0xC0, 0x00, 0x10, 0x7B, 0x00, 0x00, 0x14, 0x00]);
+        assert_eq!(cpu.r[3], 0);
+        assert!(!cpu.q);
+        assert_eq!(cpu.r[0], 0x0012);
+        assert_eq!(cpu.d, 0);
+    }
+
+    #[test]
+    fn test_memory_and_io() {
+        // R2 = 0x80, push two bytes with STXD, pop them back with LDXA,
+        // then send the byte at 0x90 out on port 3 and read port 2 in
+        let mut program = vec![0xF8, 0x80, 0xA2, 0xE2, 0xF8, 0x11, 0x73, 0xF8, 0x22, 0x73,
+                               0x60, 0x72, 0xA4, 0xF0, 0xB4,
+                               0xF8, 0x90, 0xA2, 0x63, 0x6A, 0x00];
+        program.resize(0x91, 0);
+        program[0x90] = 0x99;
+        let (cpu, ram) = run(&program);
+        assert_eq!(cpu.r[4], 0x1122);
+        assert_eq!(ram.out, vec![(3, 0x99)]);
+        assert_eq!(cpu.d, 0x42);
+        assert_eq!(ram.memory[0x91], 0x42);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut ram = Ram { memory: [0; 0x100], ef: [false; 4], out: Vec::new() };
+        // main: R1 = 0x20, R2 = 0x80, then wait; the routine sets Q and returns
+        ram.memory[..10].copy_from_slice(&[0xF8, 0x20, 0xA1, 0xF8, 0x80, 0xA2, 0x00, 0x30, 0x07, 0x00]);
+        ram.memory[0x20..0x24].copy_from_slice(&[0x78, 0x7B, 0x70, 0x00]);
+        let mut cpu = Cdp1802::new();
+        while !cpu.idle {
+            cpu.step(&mut ram);
+        }
+        assert!(cpu.interrupt());
+        assert_eq!((cpu.p, cpu.x, cpu.t), (1, 2, 0x00));
+        assert!(!cpu.interrupt());
+        for _ in 0..3 {
+            cpu.step(&mut ram);
+        }
+        assert!(cpu.q);
+        assert!(cpu.ie);
+        assert_eq!((cpu.p, cpu.x, cpu.r[0]), (0, 0, 0x0007));
+    }
+}
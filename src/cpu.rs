@@ -1,32 +1,74 @@
-use rand::Rng;
+pub mod megachip;
+pub mod runtime;
+pub mod timing;
+
+use rand::{FromEntropy, Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 use instruction;
 use instruction::Instruction::*;
 use instruction::Instruction;
-use std::io::{BufWriter, Write};
+use detect::{self, Detection};
+use framebuffer::{Framebuffer, Screen, COLOR_COLUMN_WIDTH};
+use gamedb::{GameDb, GameInfo};
+use platform::{Platform, Profile, Quirks, BIG_FONT};
+use rplflags::FLAG_COUNT;
+use self::megachip::MegaChip;
+
+// SUPER-CHIP's screen after 00FF
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 
-use CHIP8_HEIGHT;
-use CHIP8_WIDTH;
+// Where the big digits go, after the small ones
+const BIG_FONT_START: usize = 80;
 
 pub struct ExecutionState {
     pub finished: bool,
-    pub screen: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
     pub drawn: bool
 }
 
 pub struct Cpu {
     drawn       : bool,
     opcode      : u16,
-    memory      : [u8; 4096],
+    memory      : Vec<u8>,
     registers   : [u8; 16],
-    i           : u16,
+    i           : u32,
     pc          : u16,
     sp          : u16,
     stack       : [u16; 16],
-    screen      : [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+    screen      : Framebuffer,
     keys        : [bool; 16],
+    // every key Ex9E/ExA1 has looked at
+    tested_keys : [bool; 16],
     delay_timer : u8,
-    rand_gen    : rand::rngs::ThreadRng,
+    rand_gen    : StdRng,
+    quirks      : Quirks,
+    // where ROMs go, the memory, screen and font, from the platform
+    profile     : Profile,
+    // the platform picked on the command line, over the game database's
+    platform    : Option<Platform>,
+    // the platform the loaded ROM runs as, if one is known
+    target      : Option<Platform>,
+    // what the game database knows about the loaded ROM
+    game        : Option<GameInfo>,
+    // None uses the built in database
+    game_db     : Option<GameDb>,
+    // the platform guessed from the ROM when the database didn't know it
+    detection   : Option<Detection>,
+    // SUPER-CHIP's RPL user flags, and whether Fx75 has written them since
+    // take_saved_flags was last called
+    flags       : [u8; FLAG_COUNT],
+    flags_saved : bool,
+    // opcodes already decoded, by address, forgotten when their memory is
+    // written so self-modifying code still works
+    decoded     : Vec<Option<(u16, Instruction)>>,
+    decode_cache: bool,
+    // cycles the last VIP frame ran over by, owed by the next one
+    cycle_debt  : u32,
+    // MegaChip's palette, sprite size, blending and sound
+    megachip    : MegaChip,
+    // whether 00FD has stopped the program
+    exited      : bool,
 }
 
 impl Cpu
@@ -35,59 +77,128 @@ impl Cpu
         Cpu {
             drawn: false,
             opcode: 0,
-            memory: [0; 4096],
+            memory: vec![0; 4096],
             registers: [0; 16],
             i: 0,
             pc: 512,
             sp: 0,
             stack: [0; 16],
-            screen: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+            screen: Framebuffer::default(),
             keys: [false; 16],
+            tested_keys: [false; 16],
             delay_timer: 0,
-            rand_gen: rand::thread_rng()
+            rand_gen: StdRng::from_entropy(),
+            quirks: Quirks::default(),
+            profile: Profile::default(),
+            platform: None,
+            target: None,
+            game: None,
+            game_db: None,
+            detection: None,
+            flags: [0; FLAG_COUNT],
+            flags_saved: false,
+            decoded: vec![None; 4096],
+            decode_cache: true,
+            cycle_debt: 0,
+            megachip: MegaChip::default(),
+            exited: false
         }
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        if rom.len() > 4096 - 0x200 {
-            panic!("ROM is too big!")
+    // The database load_rom looks ROMs up in
+    pub fn set_game_db(&mut self, game_db: GameDb) {
+        self.game_db = Some(game_db);
+    }
+
+    pub fn game(&self) -> Option<&GameInfo> {
+        self.game.as_ref()
+    }
+
+    pub fn detection(&self) -> Option<&Detection> {
+        self.detection.as_ref()
+    }
+
+    // The same seed gives the same Cxkk numbers, for runs that need repeating
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rand_gen = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // Runs the next ROM loaded as written for this platform, with its
+    // quirks, load address, screen and font
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = Some(platform);
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    // On by default, off decodes every instruction as it runs
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+        self.invalidate(0, self.decoded.len());
+    }
+
+    // Forgets the instructions overlapping memory from start up to end
+    fn invalidate(&mut self, start: usize, end: usize) {
+        let end = end.min(self.decoded.len());
+        for decoded in &mut self.decoded[start.saturating_sub(1).min(end)..end] {
+            *decoded = None;
         }
+    }
 
-        {
-            // TODO: Use constants
-            let mut ram = BufWriter::new(&mut self.memory[0x200..4096]);
-            match ram.write_all(rom.as_ref()) {
-                Ok(_)   => (),
-                Err(_)  => panic!("Internal Error!")
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        // known games bring their own quirks and platform
+        let game_db = self.game_db.take().unwrap_or_else(GameDb::builtin);
+        self.game = game_db.lookup(&rom).cloned();
+        if let Some(quirks) = self.game.as_ref().and_then(GameInfo::quirks) {
+            self.quirks = quirks;
+        }
+        self.game_db = Some(game_db);
+
+        // otherwise go by the opcodes it uses
+        self.detection = None;
+        if self.game.is_none() {
+            let detection = detect::detect(&rom, self.platform);
+            if let Some(platform) = detection.platform {
+                self.quirks = platform.quirks();
             }
+            self.detection = Some(detection);
         }
 
-        {
-            let fonts = [
-                0xF0, 0x90, 0x90, 0x90, 0xF0,
-                0x20, 0x60, 0x20, 0x20, 0x70,
-                0xF0, 0x10, 0xF0, 0x80, 0xF0,
-                0xF0, 0x10, 0xF0, 0x10, 0xF0,
-                0x90, 0x90, 0xF0, 0x10, 0x10,
-                0xF0, 0x80, 0xF0, 0x10, 0xF0,
-                0xF0, 0x80, 0xF0, 0x90, 0xF0,
-                0xF0, 0x10, 0x20, 0x40, 0x40,
-                0xF0, 0x90, 0xF0, 0x90, 0xF0,
-                0xF0, 0x90, 0xF0, 0x10, 0xF0,
-                0xF0, 0x90, 0xF0, 0x90, 0x90,
-                0xE0, 0x90, 0xE0, 0x90, 0xE0,
-                0xF0, 0x80, 0x80, 0x80, 0xF0,
-                0xE0, 0x90, 0x90, 0x90, 0xE0,
-                0xF0, 0x80, 0xF0, 0x80, 0xF0,
-                0xF0, 0x80, 0xF0, 0x80, 0x80,
-            ];
-            let mut sprites = BufWriter::new(&mut self.memory[0x000..0x200]);
-            match sprites.write_all(&fonts) {
-                Ok(_) => (),
-                Err(_) => ()
-            }
+        self.target = self.platform
+            .or_else(|| self.game.as_ref().and_then(|game| game.platform))
+            .or_else(|| self.detection.as_ref().and_then(|detection| detection.platform));
+        if let Some(platform) = self.platform {
+            self.quirks = platform.quirks();
         }
+        self.profile = self.target.map(Platform::profile).unwrap_or_default();
 
+        let start = self.profile.load_address as usize;
+        if rom.len() > self.profile.memory_size - start {
+            panic!("ROM is too big!")
+        }
+        self.memory = vec![0; self.profile.memory_size];
+        self.memory[start..start + rom.len()].copy_from_slice(&rom);
+        self.memory[..self.profile.font.len()].copy_from_slice(self.profile.font);
+        self.memory[BIG_FONT_START..BIG_FONT_START + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
+        self.pc = self.profile.load_address;
+        self.screen = Framebuffer::new(self.profile.width, self.profile.height);
+        if self.target == Some(Platform::Chip8X) {
+            self.screen.enable_colors();
+        }
+        // the program counter only reaches the first 64K
+        self.decoded = vec![None; self.profile.memory_size.min(0x10000)];
+        self.megachip = MegaChip::default();
+        self.exited = false;
     }
 
     pub fn dump(&self) {
@@ -126,102 +237,135 @@ impl Cpu
             },
             Or {regx, regy} => {
                 self.registers[regx as usize] |= self.registers[regy as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             And {regx, regy} => {
                 self.registers[regx as usize] &= self.registers[regy as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
 
             Xor {regx, regy} => {
                 self.registers[regx as usize] ^= self.registers[regy as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
 
             Add {regx, regy} => {
                 let x = self.registers[regx as usize] as u16;
                 let y = self.registers[regy as usize] as u16;
                 let result = x.wrapping_add(y);
-                self.registers[0xF] = (result > 255) as u8;
                 // as u8 casts down to a byte to make sure we don't overflow
                 self.registers[regx as usize] = result as u8;
+                // the flag last, so it's what's left when x is F
+                self.registers[0xF] = (result > 255) as u8;
             },
             Sub {regx, regy} => {
                 let x = self.registers[regx as usize] as u16;
                 let y = self.registers[regy as usize] as u16;
                 let result = x.wrapping_sub(y);
-                self.registers[0xF] = (x > y) as u8;
                 self.registers[regx as usize] = result as u8;
+                self.registers[0xF] = (x >= y) as u8;
             },
-            Shr {reg} => {
-                let x = self.registers[reg as usize] as u16;
+            Shr {regx, regy} => {
+                let source = if self.quirks.shift_vy { regy } else { regx };
+                let x = self.registers[source as usize] as u16;
                 let result = x >> 1;
+                self.registers[regx as usize] = result as u8;
                 self.registers[0xF] = (x & 1) as u8;
-                self.registers[reg as usize] = result as u8;
             },
             Subn {regx, regy} => {
                 let x = self.registers[regx as usize] as u16;
                 let y = self.registers[regy as usize] as u16;
                 let result = y.wrapping_sub(x);
-                self.registers[0xF] = (y > x) as u8;
                 self.registers[regx as usize] = result as u8;
+                self.registers[0xF] = (y >= x) as u8;
             },
             ConstantSe {reg, byte} => {
                 let reg = self.registers[reg as usize];
                 if reg == byte {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             ConstantSne {reg, byte} => {
                 let reg = self.registers[reg as usize];
                 if reg != byte {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
-            Shl {reg} => {
-                let x = self.registers[reg as usize] as u16;
+            Shl {regx, regy} => {
+                let source = if self.quirks.shift_vy { regy } else { regx };
+                let x = self.registers[source as usize] as u16;
                 let result = x << 1;
+                self.registers[regx as usize] = result as u8;
                 self.registers[0xF] = (x >> 7) as u8;
-                self.registers[reg as usize] = result as u8;
             },
             Se {x, y} => {
                 let x = self.registers[x as usize];
                 let y = self.registers[y as usize];
                 if x == y {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             Sne {regx, regy} => {
                 let x = self.registers[regx as usize] as u16;
                 let y = self.registers[regy as usize] as u16;
                 if x != y {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
-            LdI {loc}       => { self.i = loc },
+            LdI {loc}       => { self.i = loc as u32 },
+            LdILong => {
+                self.i = self.word_at(self.pc as usize + 2) as u32;
+                // past the address too
+                self.pc += 2;
+            },
             SetLong {reg}   => {
                 for i in 0..reg+1 {
-                    self.memory[self.i as usize + i as usize] = self.registers[i as usize];
+                    self.write(self.i as usize + i as usize, self.registers[i as usize]);
+                }
+                if self.quirks.increment_i {
+                    self.i = self.i.wrapping_add(reg as u32 + 1);
                 }
             },
             LdFont {reg}    => {
                 // each digit is 5 bytes, starting at 0
                 // memory location is then just 5 * digit
-                self.i = self.registers[reg as usize] as u16 * 5;
+                self.i = self.registers[reg as usize] as u32 * 5;
             },
             LdLong {reg}    => {
                 for i in 0..reg+1 {
-                    self.registers[i as usize] = self.memory[self.i as usize + i as usize];
+                    self.registers[i as usize] = self.read(self.i as usize + i as usize);
+                }
+                if self.quirks.increment_i {
+                    self.i = self.i.wrapping_add(reg as u32 + 1);
                 }
             }
+            SaveFlags {reg} => {
+                let count = reg as usize + 1;
+                self.flags[..count].copy_from_slice(&self.registers[..count]);
+                self.flags_saved = true;
+            },
+            LoadFlags {reg} => {
+                let count = reg as usize + 1;
+                self.registers[..count].copy_from_slice(&self.flags[..count]);
+            },
             JmpA {loc}      => { 
-                self.pc = self.registers[0] as u16 + loc;
+                let reg = if self.quirks.jump_vx { (loc >> 8) & 0xF } else { 0 };
+                self.pc = self.registers[reg as usize] as u16 + loc;
                 self.pc -= 2;
             },
-            AddI {reg}      => { self.i = self.i.wrapping_add(self.registers[reg as usize] as u16) },
+            AddI {reg}      => { self.i = self.i.wrapping_add(self.registers[reg as usize] as u32) },
             LdB {reg}       => {
                 let i = self.i as usize;
                 let x = self.registers[reg as usize];
-                self.memory[i] = x.wrapping_div(100);
-                self.memory[i + 1] = (x.wrapping_div(10)) % 10;
-                self.memory[i + 2] = (x % 100) % 10;
+                self.write(i, x.wrapping_div(100));
+                self.write(i + 1, (x.wrapping_div(10)) % 10);
+                self.write(i + 2, (x % 100) % 10);
             },
             LdDelay {reg}   => {
                 self.registers[reg as usize] = self.delay_timer;
@@ -249,65 +393,234 @@ impl Cpu
                 self.registers[reg as usize] = byte;
             },
             Skp {key} => {
+                self.tested_keys[self.registers[key as usize] as usize] = true;
                 if self.keys[self.registers[key as usize] as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             Sknp {key} => {
+                self.tested_keys[self.registers[key as usize] as usize] = true;
                 if !self.keys[self.registers[key as usize] as usize] {
-                    self.pc += 2;
+                    self.skip();
+                }
+            },
+            WaitKey {reg} => {
+                match self.keys.iter().position(|&pressed| pressed) {
+                    Some(key) => self.registers[reg as usize] = key as u8,
+                    None => self.pc -= 2 // run this again until a key is down
                 }
             },
             Random {reg, byte} => {
                 let rand_num = self.rand_gen.gen_range(0, 255);
                 self.registers[reg as usize] = byte & rand_num;
             },
+            Draw {x, y, ..} if self.megachip_on() => {
+                self.drawn = true;
+                self.draw_megachip(x, y);
+            },
             Draw {x, y, n}  => {
                 self.drawn = true;
                 self.registers[0x0F] = 0;
-
-                for byte in 0..n {
-                    let y = (self.registers[y as usize] as usize + byte as usize) % CHIP8_HEIGHT; // we love casting
-                    for bit in 0..8 {
-                        let x = (self.registers[x as usize] as usize + bit) % CHIP8_WIDTH;
+                // Dxy0 is SUPER-CHIP's 16x16 sprite, two bytes a row
+                let (rows, columns) = if n == 0 { (16, 16) } else { (n as usize, 8) };
+
+                // the sprite starts on screen whatever the registers say, what
+                // runs off the edge wraps round or is cut off depending on the quirk
+                let (width, height) = (self.screen.width(), self.screen.height());
+                let left = self.registers[x as usize] as usize % width;
+                let top = self.registers[y as usize] as usize % height;
+                for row in 0..rows {
+                    let y = top + row;
+                    if y >= height && self.quirks.clip {
+                        break;
+                    }
+                    let y = y % height;
+                    for bit in 0..columns {
+                        let x = left + bit;
+                        if x >= width && self.quirks.clip {
+                            break;
+                        }
+                        let x = x % width;
 
                         // check the correct bit of the byte storing color as 1 or 0
-                        let color = (self.memory[self.i as usize + byte as usize] >> (7 - bit)) & 1;
+                        let byte = self.read(self.i as usize + row * columns / 8 + bit / 8);
+                        let color = (byte >> (7 - bit % 8)) & 1;
 
-                        if self.screen[y as usize][x as usize] == 1 && color == 1 {
+                        if self.screen.toggle(x, y, color) {
                             self.registers[0x0F] = 1;
                         }
-                        self.screen[y as usize][x as usize] ^= color;
                     }
                 }
                 
             },
             Clear => {
                 self.drawn = true;
-                self.screen = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+                self.screen.clear();
             }
+            AddNibbles {regx, regy} => {
+                let (x, y) = (self.registers[regx as usize], self.registers[regy as usize]);
+                let high = ((x >> 4) + (y >> 4)) & 7;
+                let low = ((x & 0xF) + (y & 0xF)) & 7;
+                self.registers[regx as usize] = high << 4 | low;
+            },
+            ColorZones {x, y} => {
+                // the low nibbles are the first column and zone of 4 rows,
+                // the high nibbles how many more after them
+                let (across, down) = (self.registers[x as usize] as usize, self.registers[(x as usize + 1) & 0xF] as usize);
+                let columns = (across & 0xF)..(across & 0xF) + (across >> 4) + 1;
+                let rows = (down & 0xF) * 4..((down & 0xF) + (down >> 4) + 1) * 4;
+                let color = self.registers[y as usize];
+                if let Some(colors) = self.screen.colors_mut() {
+                    colors.fill(columns, rows, color);
+                }
+            },
+            ColorRows {x, y, n} => {
+                // like Bxy0, V[x] and V[x + 1] say where and V[y] the color,
+                // but in pixels rather than zones
+                let column = self.registers[x as usize] as usize / COLOR_COLUMN_WIDTH;
+                let top = self.registers[(x as usize + 1) & 0xF] as usize;
+                let color = self.registers[y as usize];
+                if let Some(colors) = self.screen.colors_mut() {
+                    colors.fill(column..column + 1, top..top + n as usize, color);
+                }
+            },
+            NextBackground => {
+                if let Some(colors) = self.screen.colors_mut() {
+                    colors.next_background();
+                }
+            },
+            MegaOff | MegaOn | LdIHuge {..} | LoadPalette {..} | SpriteWidth {..} | SpriteHeight {..} |
+            ScreenAlpha {..} | PlaySample {..} | StopSample | BlendMode {..} => self.run_megachip_op(instr),
+            // only the one keypad is hooked up, nothing is ever down on the second
+            Skp2 {..} => (),
+            Sknp2 {..} => self.skip(),
+            // and nothing is plugged into port 3
+            Input {reg} => self.registers[reg as usize] = 0,
+            // a new screen of the other size, blank as SUPER-CHIP leaves it.
+            // MegaChip's color screen stays as it is until 0010.
+            HighRes | LowRes if self.megachip_on() => (),
+            HighRes => {
+                self.drawn = true;
+                self.screen = Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT);
+            },
+            LowRes => {
+                self.drawn = true;
+                self.screen = Framebuffer::new(self.profile.width, self.profile.height);
+            },
+            // scrolling by 4 pixels sideways, XO-CHIP's 00Dn scrolls up
+            ScrollDown {n} => self.screen.scroll(0, n as isize),
+            ScrollUp {n} => self.screen.scroll(0, -(n as isize)),
+            ScrollRight => self.screen.scroll(4, 0),
+            ScrollLeft => self.screen.scroll(-4, 0),
+            Exit => self.exited = true,
+            BigFont {reg} => {
+                // 10 bytes a digit
+                self.i = (BIG_FONT_START + (self.registers[reg as usize] & 0xF) as usize * 10) as u32;
+            },
+            // other platforms' instructions, which only the detector looks at for now
+            Sys {..} | SaveRange {..} |
+            LoadRange {..} | Plane {..} | Audio | Pitch {..} | Output {..} => (),
             Unknown         => ()
         }
     }
 
-    pub fn step(&mut self, keys: [bool; 16]) -> ExecutionState {
-        self.keys = keys;
-        self.drawn = false;
-        let pc = self.pc as usize;
-        self.opcode = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
-        let instr = instruction::convert_op(self.opcode);
+    pub fn screen(&self) -> &Screen {
+        self.screen.pixels()
+    }
+
+    // Whether the screen was drawn to since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        self.screen.take_dirty()
+    }
+
+    // Flags kept from an earlier run
+    pub fn set_flags(&mut self, flags: [u8; FLAG_COUNT]) {
+        self.flags = flags;
+    }
+
+    // The flags, if Fx75 has saved them since the last call
+    pub fn take_saved_flags(&mut self) -> Option<[u8; FLAG_COUNT]> {
+        if self.flags_saved {
+            self.flags_saved = false;
+            Some(self.flags)
+        } else {
+            None
+        }
+    }
 
+    pub fn tested_keys(&self) -> [bool; 16] {
+        self.tested_keys
+    }
+
+    // timers count down at 60 Hz no matter how fast instructions run
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
+    }
+
+    // Addresses past the end of memory wrap round to the start, the way the
+    // DREAM 6800's 2K shows up again and again in its 4K address space
+    fn read(&self, address: usize) -> u8 {
+        self.memory[address % self.memory.len()]
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        let address = address % self.memory.len();
+        self.memory[address] = value;
+        self.invalidate(address, address + 1);
+    }
+
+    // The big endian word at an address
+    fn word_at(&self, at: usize) -> u16 {
+        (self.read(at) as u16) << 8 | self.read(at + 1) as u16
+    }
+
+    // An opcode the way the loaded ROM's platform reads it. Only XO-CHIP
+    // has F000 nnnn, elsewhere its address would point past the memory.
+    fn convert(&self, op: u16) -> Instruction {
+        match instruction::convert_platform_op(op, self.target) {
+            LdILong if self.target != Some(Platform::XoChip) => Unknown,
+            instr => instr
+        }
+    }
+
+    // Hops over the next instruction, which may take two words
+    fn skip(&mut self) {
+        let op = self.word_at(self.pc as usize + 2);
+        self.pc += self.convert(op).length() as u16;
+    }
+
+    // The instruction at pc, from the cache when it's there
+    fn decode(&mut self) -> Instruction {
+        let pc = self.pc as usize;
+        match self.decoded.get(pc).cloned().flatten() {
+            Some((opcode, instr)) => {
+                self.opcode = opcode;
+                instr
+            },
+            None => {
+                self.opcode = self.word_at(pc);
+                let instr = self.convert(self.opcode);
+                if self.decode_cache && pc < self.decoded.len() {
+                    self.decoded[pc] = Some((self.opcode, instr));
+                }
+                instr
+            }
+        }
+    }
+
+    pub fn step(&mut self, keys: [bool; 16]) -> ExecutionState {
+        self.keys = keys;
+        self.drawn = false;
+        let instr = self.decode();
 
         self.run_op(instr);
         self.pc += 2;
         
-        let finished = self.memory.len() <= self.pc as usize;
+        let finished = self.exited || self.memory.len() <= self.pc as usize;
         return ExecutionState {
             finished: finished,
-            screen: self.screen,
             drawn: self.drawn
         }
     }
@@ -317,7 +630,10 @@ impl Cpu
 #[cfg(test)]
 mod tests {
     use cpu::Cpu;
+    use gamedb::GameDb;
     use instruction::Instruction::*;
+    use platform::Platform;
+    use sha1;
 
     fn start() -> Cpu
     {
@@ -358,7 +674,7 @@ mod tests {
     #[test]
     fn test_shr() {
         let mut processor = start();
-        processor.run_op(Shr {reg: 1 }); // V1 >> 1
+        processor.run_op(Shr {regx: 1, regy: 1 }); // V1 >> 1
         assert_eq!(processor.registers[1], 10);
         assert_eq!(processor.registers[0xF], 0)
     }
@@ -374,7 +690,7 @@ mod tests {
     #[test]
     fn test_shl() {
         let mut processor = start();
-        processor.run_op(Shl {reg: 1}); // V1 << 1
+        processor.run_op(Shl {regx: 1, regy: 1}); // V1 << 1
         assert_eq!(processor.registers[1], 40);
         assert_eq!(processor.registers[0xF], 0);
     }
@@ -444,6 +760,249 @@ mod tests {
         assert_eq!(processor.registers[1], 0x4);
     }
 
+    #[test]
+    fn test_tick_timers() {
+        let mut processor = start();
+        processor.run_op(SetDelay {reg: 1}); // delay = 20
+        processor.step([false; 16]);
+        assert_eq!(processor.delay_timer, 20);
+        processor.tick_timers();
+        assert_eq!(processor.delay_timer, 19);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut processor = Cpu::new();
+        processor.set_game_db(GameDb::parse("").unwrap());
+        processor.load_rom(vec![
+            0x61, 0x07, // V1 = 7
+            0xA2, 0x01, // I = 0x201, the 7 above
+            0x60, 0x09, // V0 = 9
+            0xF0, 0x55, // making the first instruction V1 = 9
+            0x12, 0x00, // and run it again
+        ]);
+        for _ in 0..5 {
+            processor.step([false; 16]);
+        }
+        assert_eq!(processor.registers[1], 7);
+        processor.step([false; 16]);
+        assert_eq!(processor.registers[1], 9);
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let mut processor = start();
+        processor.set_flags([9; 16]);
+        processor.run_op(LoadFlags {reg: 1});
+        assert_eq!(processor.registers[..3], [9, 9, 0]);
+        assert_eq!(processor.take_saved_flags(), None);
+
+        processor.registers[0] = 4;
+        processor.run_op(SaveFlags {reg: 0});
+        let flags = processor.take_saved_flags().unwrap();
+        assert_eq!(flags[..2], [4, 9]);
+        assert_eq!(processor.take_saved_flags(), None);
+    }
+
+    #[test]
+    fn test_wait_key() {
+        let mut processor = start();
+        let pc = processor.pc;
+        processor.run_op(WaitKey {reg: 3});
+        assert_eq!(processor.pc, pc - 2);
+
+        processor.keys[0xB] = true;
+        processor.run_op(WaitKey {reg: 3});
+        assert_eq!(processor.registers[3], 0xB);
+    }
+
+    #[test]
+    fn test_tested_keys() {
+        let mut processor = start();
+        processor.registers[2] = 6;
+        processor.run_op(Sknp {key: 2});
+        assert!(processor.tested_keys()[6]);
+        assert!(!processor.tested_keys()[5]);
+    }
+
+    #[test]
+    fn test_quirks() {
+        let mut processor = start();
+        processor.set_quirks(Platform::Chip8.quirks());
+        processor.registers[0xF] = 1;
+        processor.run_op(Shr {regx: 2, regy: 1}); // V2 = V1 >> 1
+        assert_eq!(processor.registers[2], 10);
+        processor.run_op(Or {regx: 7, regy: 1});
+        assert_eq!(processor.registers[0xF], 0);
+        processor.run_op(SetLong {reg: 2});
+        assert_eq!(processor.i, 10);
+
+        processor.set_quirks(Platform::Schip.quirks());
+        processor.registers[2] = 4;
+        processor.run_op(JmpA {loc: 0x234}); // V2 + 0x234
+        assert_eq!(processor.pc, 0x236);
+    }
+
+    #[test]
+    fn test_clip_quirk() {
+        let mut processor = start();
+        // a 2 line sprite at the bottom right corner
+        processor.memory[0x300] = 0xFF;
+        processor.memory[0x301] = 0xFF;
+        processor.i = 0x300;
+        processor.registers[1] = 60;
+        processor.registers[2] = 31;
+        processor.run_op(Draw {x: 1, y: 2, n: 2});
+        assert_eq!(processor.screen()[0][0], 1);
+
+        processor.run_op(Clear);
+        processor.set_quirks(Platform::Chip8.quirks());
+        processor.run_op(Draw {x: 1, y: 2, n: 2});
+        assert_eq!(processor.screen()[0][0], 0);
+        assert_eq!(processor.screen()[31][63], 1);
+    }
+
+    #[test]
+    fn test_resolution() {
+        let mut processor = start();
+        processor.run_op(HighRes);
+        assert_eq!((processor.screen().width(), processor.screen().height()), (128, 64));
+        processor.registers[1] = 120;
+        processor.memory[7] = 0x80;
+        processor.run_op(Draw {x: 1, y: 7, n: 1}); // a pixel at 120, 12
+        assert_eq!(processor.screen()[12][120], 1);
+        processor.run_op(LowRes);
+        assert_eq!((processor.screen().width(), processor.screen().height()), (64, 32));
+    }
+
+    #[test]
+    fn test_big_sprite() {
+        let mut processor = start();
+        processor.run_op(HighRes);
+        // 16 rows of two bytes, the right half of each row lit
+        for row in 0..16 {
+            processor.memory[0x300 + row * 2 + 1] = 0xFF;
+        }
+        processor.i = 0x300;
+        processor.run_op(Draw {x: 2, y: 2, n: 0});
+        assert_eq!(processor.screen()[15][..16], [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(processor.screen()[16][8], 0);
+    }
+
+    #[test]
+    fn test_load_rom_applies_game_db() {
+        let rom = vec![0x00, 0xE0];
+        let mut processor = Cpu::new();
+        let entry = format!("[{}]\ntitle = \"Clear\"\nplatform = \"schip\"\n", sha1::hex_digest(&rom));
+        processor.set_game_db(GameDb::parse(&entry).unwrap());
+        processor.load_rom(rom);
+        assert_eq!(processor.game().and_then(|game| game.title.clone()), Some(String::from("Clear")));
+        assert_eq!(processor.quirks(), Platform::Schip.quirks());
+    }
+
+    #[test]
+    fn test_load_rom_detects_platform() {
+        let mut processor = Cpu::new();
+        processor.set_game_db(GameDb::parse("").unwrap());
+        processor.load_rom(vec![0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(processor.detection().and_then(|detection| detection.platform), Some(Platform::Schip));
+        assert_eq!(processor.quirks(), Platform::Schip.quirks());
+    }
+
+    #[test]
+    fn test_load_rom_uses_platform_profile() {
+        let mut processor = Cpu::new();
+        processor.set_game_db(GameDb::parse("").unwrap());
+        processor.set_platform(Platform::Eti660);
+        processor.load_rom(vec![0x00, 0xE0]);
+        assert_eq!(processor.pc, 0x600);
+        assert_eq!(processor.memory[0x601], 0xE0);
+        assert_eq!(processor.screen().height(), 48);
+
+        // sprites wrap around the taller screen
+        processor.i = 0x600;
+        processor.registers[1] = 40;
+        processor.run_op(Draw {x: 0, y: 1, n: 2});
+        assert_eq!(processor.screen()[41][0], 1);
+    }
+
+    #[test]
+    fn test_long_index_only_on_xochip() {
+        let rom = vec![0xF0, 0x00, 0xFF, 0xFF, 0xF0, 0x55];
+        let mut processor = Cpu::new();
+        processor.set_game_db(GameDb::parse("").unwrap());
+        processor.set_platform(Platform::Chip8);
+        processor.load_rom(rom.clone());
+        processor.step([false; 16]);
+        assert_eq!((processor.i, processor.pc), (0, 0x202));
+
+        processor.set_platform(Platform::XoChip);
+        processor.load_rom(rom);
+        processor.step([false; 16]);
+        assert_eq!((processor.i, processor.pc), (0xFFFF, 0x204));
+        // what's past the end of memory wraps round to the start
+        processor.registers[0] = 9;
+        processor.step([false; 16]);
+        assert_eq!(processor.memory[0xFFF], 9);
+        processor.run_op(LdB {reg: 0});
+        processor.run_op(LdLong {reg: 2});
+        processor.run_op(Draw {x: 0, y: 0, n: 3});
+        assert_eq!(processor.registers[..3], [0, 0, 9]);
+    }
+
+    #[test]
+    fn test_dream6800_mirrors_memory() {
+        let mut processor = Cpu::new();
+        processor.set_game_db(GameDb::parse("").unwrap());
+        processor.set_platform(Platform::Dream6800);
+        // V0 = 7, I = 0x900, save V0, I = 0x900, draw a row of it, jump to 0x7FF
+        processor.load_rom(vec![0x60, 0x07, 0xA9, 0x00, 0xF0, 0x55, 0xA9, 0x00, 0xD1, 0x11, 0x17, 0xFF]);
+        for _ in 0..6 {
+            processor.step([false; 16]);
+        }
+        assert_eq!(processor.memory[0x100], 7);
+        assert_eq!(processor.screen()[0][5..8], [1, 1, 1]);
+        assert_eq!(processor.pc, 0x7FF);
+        processor.step([false; 16]);
+    }
+
+    #[test]
+    fn test_chip8x() {
+        let mut processor = Cpu::new();
+        processor.set_game_db(GameDb::parse("").unwrap());
+        processor.set_platform(Platform::Chip8X);
+        // color columns 1 and 2 of the top zone, then cycle the background
+        processor.load_rom(vec![0x60, 0x11, 0x61, 0x00, 0x62, 0x04, 0xB0, 0x20, 0x02, 0xA0]);
+        assert_eq!(processor.pc, 0x300);
+        for _ in 0..5 {
+            processor.step([false; 16]);
+        }
+        let colors = processor.screen().colors().unwrap();
+        assert_eq!(colors.foreground(8, 3), 4);
+        assert_eq!(colors.foreground(23, 0), 4);
+        assert_eq!(colors.foreground(24, 0), 1);
+        assert_eq!(colors.foreground(8, 4), 1);
+        assert_eq!(colors.background(), 0);
+
+        processor.registers[3] = 0x76;
+        processor.registers[4] = 0x23;
+        processor.run_op(AddNibbles {regx: 3, regy: 4});
+        assert_eq!(processor.registers[3], 0x11);
+
+        // 3 rows from (20, 5) in color 2, which is the 8 pixel strip 16 to 23
+        processor.registers[5] = 20;
+        processor.registers[6] = 5;
+        processor.registers[7] = 2;
+        processor.run_op(ColorRows {x: 5, y: 7, n: 3});
+        let colors = processor.screen().colors().unwrap();
+        assert_eq!(colors.foreground(16, 5), 2);
+        assert_eq!(colors.foreground(23, 7), 2);
+        assert_eq!(colors.foreground(16, 4), 1);
+        assert_eq!(colors.foreground(16, 8), 1);
+        assert_eq!(colors.foreground(24, 5), 1);
+        assert_eq!(colors.foreground(15, 5), 1);
+    }
+
     #[test]
     fn test_xor() {
         let mut processor = start();
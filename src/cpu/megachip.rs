@@ -0,0 +1,291 @@
+use cpu::Cpu;
+use framebuffer::Framebuffer;
+use instruction::Instruction;
+use instruction::Instruction::*;
+use palette::Rgb;
+
+// The screen 0011 switches to
+pub const MEGA_WIDTH: usize = 256;
+pub const MEGA_HEIGHT: usize = 192;
+
+// Ahead of a sample's data: its rate in Hz (2 bytes), its length (3 bytes)
+// and a byte that's always 0
+const SAMPLE_HEADER: usize = 6;
+
+// How sprite pixels mix with what's under them, from 080n
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Blend {
+    // over it, as opaque as the palette says, times this many quarters
+    Alpha(u8),
+    // added to it
+    Add,
+    // multiplied with it
+    Multiply,
+}
+
+impl Blend {
+    fn from_mode(n: u8) -> Blend {
+        match n {
+            1 => Blend::Alpha(1),
+            2 => Blend::Alpha(2),
+            3 => Blend::Alpha(3),
+            4 => Blend::Add,
+            5 => Blend::Multiply,
+            _ => Blend::Alpha(4)
+        }
+    }
+
+    // A sprite color with its alpha over the color under it
+    pub fn mix(self, under: Rgb, color: Rgb, alpha: u8) -> Rgb {
+        let alpha = alpha as u32;
+        let channel = |under: u8, color: u8| -> u8 {
+            let (under, color) = (under as u32, color as u32);
+            match self {
+                Blend::Alpha(quarters) => {
+                    let alpha = alpha * quarters as u32 / 4;
+                    ((color * alpha + under * (255 - alpha)) / 255) as u8
+                },
+                Blend::Add => (under + color * alpha / 255).min(255) as u8,
+                Blend::Multiply => {
+                    let product = under * color / 255;
+                    ((product * alpha + under * (255 - alpha)) / 255) as u8
+                }
+            }
+        };
+        (channel(under.0, color.0), channel(under.1, color.1), channel(under.2, color.2))
+    }
+}
+
+// A digitized sound playing from memory
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Sample {
+    start: usize,
+    length: usize,
+    rate: u32,
+    looped: bool,
+    // how far through it, in samples
+    position: f64,
+}
+
+// What MegaChip adds to the machine
+pub struct MegaChip {
+    // whether 0011 has switched to the color screen
+    on: bool,
+    // alpha and color of each palette index, 0 is never drawn
+    palette: Vec<(u8, Rgb)>,
+    sprite_width: usize,
+    sprite_height: usize,
+    blend: Blend,
+    sample: Option<Sample>,
+}
+
+impl Default for MegaChip {
+    fn default() -> MegaChip {
+        MegaChip {
+            on: false,
+            palette: vec![(0, (0, 0, 0)); 256],
+            sprite_width: 8,
+            sprite_height: 8,
+            blend: Blend::Alpha(4),
+            sample: None,
+        }
+    }
+}
+
+impl Cpu {
+    // The instructions only MegaChip has
+    pub(super) fn run_megachip_op(&mut self, instr: Instruction) {
+        match instr {
+            MegaOn => {
+                self.megachip.on = true;
+                self.screen = Framebuffer::new(MEGA_WIDTH, MEGA_HEIGHT);
+                self.screen.enable_true_color();
+            },
+            MegaOff => {
+                self.megachip.on = false;
+                self.screen = Framebuffer::new(self.profile.width, self.profile.height);
+            },
+            LdIHuge {high} => {
+                let pc = self.pc as usize;
+                let low = (self.read(pc + 2) as u32) << 8 | self.read(pc + 3) as u32;
+                self.i = (high as u32) << 16 | low;
+                // past the address too
+                self.pc += 2;
+            },
+            LoadPalette {n} => {
+                // 4 bytes a color, alpha first, into indexes 1 and up
+                for color in 0..n as usize {
+                    let at = self.i as usize + color * 4;
+                    let (a, r, g, b) = (self.read(at), self.read(at + 1), self.read(at + 2), self.read(at + 3));
+                    self.megachip.palette[color + 1] = (a, (r, g, b));
+                }
+            },
+            SpriteWidth {n} => self.megachip.sprite_width = if n == 0 { 256 } else { n as usize },
+            SpriteHeight {n} => self.megachip.sprite_height = if n == 0 { 256 } else { n as usize },
+            ScreenAlpha {alpha} => self.screen.set_alpha(alpha),
+            PlaySample {n} => {
+                let i = self.i as usize;
+                let rate = (self.read(i) as u32) << 8 | self.read(i + 1) as u32;
+                let length = (self.read(i + 2) as usize) << 16 | (self.read(i + 3) as usize) << 8 | self.read(i + 4) as usize;
+                self.megachip.sample = Some(Sample { start: i + SAMPLE_HEADER, length, rate, looped: n == 0, position: 0.0 });
+            },
+            StopSample => self.megachip.sample = None,
+            BlendMode {n} => self.megachip.blend = Blend::from_mode(n),
+            _ => ()
+        }
+    }
+
+    // Whether Dxyn draws MegaChip's color sprites
+    pub(super) fn megachip_on(&self) -> bool {
+        self.megachip.on
+    }
+
+    // A sprite_width by sprite_height sprite at I, a palette index a byte,
+    // blended in at V[x], V[y]. Index 0 is left out and anything past the
+    // edge is cut off. VF is set if it covered anything.
+    pub(super) fn draw_megachip(&mut self, x: u8, y: u8) {
+        self.registers[0xF] = 0;
+        let (left, top) = (self.registers[x as usize] as usize, self.registers[y as usize] as usize);
+        let (width, height) = (self.megachip.sprite_width, self.megachip.sprite_height);
+        for row in 0..height.min(MEGA_HEIGHT.saturating_sub(top)) {
+            for column in 0..width.min(MEGA_WIDTH.saturating_sub(left)) {
+                let index = self.read(self.i as usize + row * width + column);
+                if index == 0 {
+                    continue;
+                }
+                let (x, y) = (left + column, top + row);
+                let under = self.screen.pixels().true_color().map_or((0, 0, 0), |true_color| true_color.pixels[y * MEGA_WIDTH + x]);
+                let (alpha, color) = self.megachip.palette[index as usize];
+                let color = self.megachip.blend.mix(under, color, alpha);
+                if self.screen.paint(x, y, index, color) {
+                    self.registers[0xF] = 1;
+                }
+            }
+        }
+    }
+
+    // Whether a sample is playing
+    pub fn playing_sample(&self) -> bool {
+        self.megachip.sample.is_some()
+    }
+
+    // The next out.len() samples of sound, 8 bit unsigned at rate Hz.
+    // Silence once the sample has finished, unless it loops.
+    pub fn sample_audio(&mut self, out: &mut [u8], rate: u32) {
+        for value in out.iter_mut() {
+            *value = 0x80;
+            let sample = match self.megachip.sample {
+                Some(sample) => sample,
+                None => continue
+            };
+            let mut position = sample.position;
+            if position as usize >= sample.length {
+                if !sample.looped || sample.length == 0 {
+                    self.megachip.sample = None;
+                    continue;
+                }
+                position %= sample.length as f64;
+            }
+            *value = self.read(sample.start + position as usize);
+            self.megachip.sample = Some(Sample { position: position + sample.rate as f64 / rate as f64, ..sample });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use cpu::megachip::{Blend, MEGA_WIDTH};
+    use gamedb::GameDb;
+    use instruction::Instruction::*;
+    use platform::Platform;
+
+    fn megachip(rom: Vec<u8>) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_game_db(GameDb::parse("").unwrap());
+        cpu.set_platform(Platform::MegaChip);
+        cpu.load_rom(rom);
+        cpu
+    }
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(Blend::Alpha(4).mix((0, 0, 0), (200, 100, 0), 255), (200, 100, 0));
+        assert_eq!(Blend::Alpha(2).mix((0, 0, 0), (200, 100, 0), 255), (99, 49, 0));
+        assert_eq!(Blend::Add.mix((100, 200, 0), (100, 100, 0), 255), (200, 255, 0));
+        assert_eq!(Blend::Multiply.mix((255, 100, 0), (51, 255, 9), 255), (51, 100, 0));
+    }
+
+    #[test]
+    fn test_draw() {
+        // big screen, I = 0x000300, a 2x1 sprite, palette of 1 color at I
+        let mut cpu = megachip(vec![0x00, 0x11, 0x01, 0x00, 0x03, 0x00, 0x03, 0x02, 0x04, 0x01, 0x02, 0x01]);
+        cpu.memory[0x300..0x304].copy_from_slice(&[0xFF, 0x10, 0x20, 0x30]);
+        for _ in 0..5 {
+            cpu.step([false; 16]);
+        }
+        assert_eq!(cpu.i, 0x300);
+        assert_eq!((cpu.screen().width(), cpu.screen().height()), (256, 192));
+
+        // index 1 then 0, which is left out
+        cpu.memory[0x300] = 1;
+        cpu.memory[0x301] = 0;
+        cpu.registers[0] = 254;
+        cpu.registers[1] = 191;
+        cpu.run_op(Draw {x: 0, y: 1, n: 0});
+        let true_color = cpu.screen().true_color().unwrap();
+        assert_eq!(true_color.pixels[191 * MEGA_WIDTH + 254], (0x10, 0x20, 0x30));
+        assert_eq!(true_color.pixels[191 * MEGA_WIDTH + 255], (0, 0, 0));
+        assert_eq!(cpu.registers[0xF], 0);
+        cpu.run_op(Draw {x: 0, y: 1, n: 0});
+        assert_eq!(cpu.registers[0xF], 1);
+
+        cpu.run_op(MegaOff);
+        assert_eq!(cpu.screen().width(), 64);
+    }
+
+    #[test]
+    fn test_skip_long_address() {
+        // skip if V0 == 0 hops over all 4 bytes of 01nn nnnn
+        let mut cpu = megachip(vec![0x30, 0x00, 0x01, 0x12, 0x34, 0x56, 0x60, 0x05]);
+        cpu.step([false; 16]);
+        assert_eq!(cpu.pc, 0x206);
+        cpu.step([false; 16]);
+        assert_eq!(cpu.registers[0], 5);
+        assert_eq!(cpu.i, 0);
+    }
+
+    #[test]
+    fn test_superchip_instructions() {
+        // hires, V0 = 8, I = big 8, draw it, scroll down 2 and left, exit
+        let mut cpu = megachip(vec![0x00, 0xFF, 0x60, 0x08, 0xF0, 0x30, 0xD1, 0x1A, 0x00, 0xC2, 0x00, 0xFC, 0x00, 0xFD]);
+        for _ in 0..6 {
+            assert!(!cpu.step([false; 16]).finished);
+        }
+        assert_eq!((cpu.screen().width(), cpu.screen().height()), (128, 64));
+        // the big 8's top left corner, moved 2 down and 4 left off the edge
+        assert_eq!(cpu.screen()[2][..4], [1, 1, 1, 1]);
+        assert_eq!(cpu.screen()[4][..4], [0, 0, 1, 1]);
+        assert_eq!(cpu.screen()[2][4..12], [0; 8]);
+        assert!(cpu.step([false; 16]).finished);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut cpu = megachip(vec![0x00, 0xE0]);
+        // 4 samples at 11025 Hz, played once at 22050 Hz
+        cpu.memory[0x400..0x40A].copy_from_slice(&[0x2B, 0x11, 0, 0, 4, 0, 10, 20, 30, 40]);
+        cpu.i = 0x400;
+        cpu.run_op(PlaySample {n: 1});
+        let mut out = [0; 10];
+        cpu.sample_audio(&mut out, 22050);
+        assert_eq!(out, [10, 10, 20, 20, 30, 30, 40, 40, 0x80, 0x80]);
+        assert!(!cpu.playing_sample());
+
+        cpu.run_op(PlaySample {n: 0});
+        cpu.sample_audio(&mut out, 11025);
+        assert_eq!(out[..6], [10, 20, 30, 40, 10, 20]);
+        cpu.run_op(StopSample);
+        assert!(!cpu.playing_sample());
+    }
+}
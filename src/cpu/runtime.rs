@@ -0,0 +1,161 @@
+use sdl2;
+
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpu::Cpu;
+use frontend::Renderer;
+use gamedb::GameDb;
+use graphics::Graphics;
+use graphics::persistence::Persistence;
+use graphics::scaler::ScaleOptions;
+use instruction;
+use keyboard::Keyboard;
+use keymap::KeyMap;
+use palette::Palette;
+use platform::Quirks;
+
+// What programs from the recompiler run on: the interpreter's machine, and
+// its instructions for whatever they don't do themselves.
+impl Cpu {
+    #[inline]
+    pub fn v(&mut self) -> &mut [u8; 16] {
+        &mut self.registers
+    }
+
+    #[inline]
+    pub fn index(&mut self) -> &mut u32 {
+        &mut self.i
+    }
+
+    #[inline]
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Runs one instruction that doesn't touch the program counter
+    #[inline]
+    pub fn exec(&mut self, op: u16) {
+        self.run_op(instruction::convert_op(op));
+    }
+
+    // Whether the key in V[x] is down, as Ex9E and ExA1 test it
+    #[inline]
+    pub fn key(&mut self, x: usize) -> bool {
+        let key = self.registers[x] as usize;
+        self.tested_keys[key] = true;
+        self.keys[key]
+    }
+
+    // The key Fx0A would take
+    pub fn pressed_key(&self) -> Option<u8> {
+        self.keys.iter().position(|&pressed| pressed).map(|key| key as u8)
+    }
+
+    pub fn call(&mut self, back: u16) {
+        self.stack[self.sp as usize] = back;
+        self.sp += 1;
+    }
+
+    pub fn ret(&mut self) -> u16 {
+        self.sp -= 1;
+        self.stack[self.sp as usize]
+    }
+
+    // Runs the instruction at pc through the interpreter, for code that
+    // wasn't recompiled, and says where to go next
+    pub fn interpret(&mut self, pc: u16) -> u16 {
+        self.pc = pc;
+        let keys = self.keys;
+        self.step(keys);
+        self.pc
+    }
+}
+
+// A program: runs from pc until its budget of instructions is spent and
+// says where to carry on next time
+pub type Program = fn(&mut Cpu, u16, &mut u32) -> u16;
+
+// The interpreter as a Program, to compare recompiled ones with
+pub fn interpreted(cpu: &mut Cpu, mut pc: u16, budget: &mut u32) -> u16 {
+    while *budget > 0 {
+        *budget -= 1;
+        pc = cpu.interpret(pc);
+    }
+    pc
+}
+
+// One 60th of a second: `ipf` instructions with the keys held, then the timers
+pub fn run_frame(cpu: &mut Cpu, program: Program, keys: [bool; 16], ipf: u32) {
+    cpu.keys = keys;
+    let mut budget = ipf;
+    let pc = cpu.pc;
+    cpu.pc = program(cpu, pc, &mut budget);
+    cpu.tick_timers();
+}
+
+// The screen as text, # for lit pixels
+pub fn screen_text(cpu: &Cpu) -> String {
+    cpu.screen().rows()
+        .map(|row| row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }).chain(Some('\n')).collect::<String>())
+        .collect()
+}
+
+// The main function of a recompiled program:
+//
+//     PROGRAM [--ipf N]                     plays it in a window
+//     PROGRAM --frames N [--seed N] [--interpret]
+//
+// The second form runs N frames without input and prints the screen, with
+// Cxkk seeded so the recompiled code and the interpreter can be compared.
+pub fn main(rom: &[u8], quirks: Quirks, program: Program) {
+    let (mut frames, mut ipf, mut seed, mut interpret) = (None, 10, 0, false);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| args.next().and_then(|n| n.parse().ok())
+            .unwrap_or_else(|| panic!("{} needs a number", name));
+        match arg.as_str() {
+            "--frames" => frames = Some(number("--frames")),
+            "--ipf" => ipf = number("--ipf") as u32,
+            "--seed" => seed = number("--seed"),
+            "--interpret" => interpret = true,
+            _ => panic!("Unknown option {}", arg)
+        }
+    }
+
+    let mut cpu = Cpu::new();
+    cpu.set_game_db(GameDb::default());
+    cpu.load_rom(rom.to_vec());
+    cpu.set_quirks(quirks);
+    cpu.seed_random(seed);
+    let program = if interpret { interpreted } else { program };
+
+    match frames {
+        Some(frames) => {
+            for _ in 0..frames {
+                run_frame(&mut cpu, program, [false; 16], ipf);
+            }
+            print!("{}", screen_text(&cpu));
+        },
+        None => play(&mut cpu, program, ipf)
+    }
+}
+
+fn play(cpu: &mut Cpu, program: Program, ipf: u32) {
+    let context = sdl2::init().unwrap();
+    let mut graphics = Graphics::new(&context, Palette::default(), Persistence::Off, ScaleOptions::default());
+    let mut keyboard = Keyboard::new(&context, KeyMap::default());
+    let frame_time = Duration::from_secs(1) / 60;
+
+    while let Some(keys) = keyboard.get_keys() {
+        let frame_start = Instant::now();
+        run_frame(cpu, program, keys, ipf);
+        if cpu.take_dirty() || graphics.fading() {
+            graphics.draw(cpu.screen());
+        }
+        if let Some(rest) = frame_time.checked_sub(frame_start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use cpu::Cpu;
+use instruction::Instruction;
+use instruction::Instruction::*;
+
+// How long things took on the COSMAC VIP, in CDP1802 machine cycles of 8
+// clocks at 1.7609 MHz. That's 3668 cycles every 60th of a second, and the
+// display interrupt and the CDP1861's DMA take 1832 of them.
+pub const FRAME_CYCLES: u32 = 3668;
+pub const DISPLAY_CYCLES: u32 = 1832;
+
+// What the interpreter spends fetching and decoding each instruction
+const FETCH_CYCLES: u32 = 40;
+
+// What a frame of VIP timing ran
+pub struct TimedFrame {
+    pub instructions: u32,
+    pub finished: bool,
+}
+
+impl Cpu {
+    // The cycles the VIP interpreter takes over the instruction at pc, for the
+    // machine as it is now
+    pub fn cycles(&self, instr: Instruction) -> u32 {
+        let v = |reg: u8| self.registers[reg as usize];
+        // skips take a little longer when they skip
+        let skip = |skipped: bool| if skipped { 4 } else { 0 };
+
+        FETCH_CYCLES + match instr {
+            Clear => 3078,
+            Ret => 10,
+            Jmp {..} => 12,
+            Call {..} => 26,
+            Sys {..} => 18,
+            ConstantSe {reg, byte} => 10 + skip(v(reg) == byte),
+            ConstantSne {reg, byte} => 10 + skip(v(reg) != byte),
+            Se {x, y} => 14 + skip(v(x) == v(y)),
+            Sne {regx, regy} => 14 + skip(v(regx) != v(regy)),
+            Set {..} => 6,
+            AddO {..} => 10,
+            Load {..} | Or {..} | And {..} | Xor {..} | Add {..} | Sub {..} | Subn {..} | Shr {..} | Shl {..} => 44,
+            LdI {..} => 12,
+            JmpA {..} => 22,
+            Random {..} => 36,
+            Draw {x, n, ..} => {
+                // rows that straddle two bytes of the display take longer
+                let row = if v(x) % 8 == 0 { 46 } else { 68 };
+                26 + row * n as u32
+            },
+            Skp {key} => 14 + skip(self.keys[v(key) as usize & 0xF]),
+            Sknp {key} => 14 + skip(!self.keys[v(key) as usize & 0xF]),
+            LdDelay {..} | SetDelay {..} | WaitKey {..} => 10,
+            AddI {..} | LdFont {..} => 16,
+            // digits are worked out by repeated subtraction
+            LdB {reg} => {
+                let x = v(reg) as u32;
+                80 + 16 * (x / 100 + x / 10 % 10 + x % 10)
+            },
+            SetLong {reg} | LdLong {reg} => 14 + 14 * (reg as u32 + 1),
+            // the VIP had none of the others
+            _ => 0
+        }
+    }
+
+    // A 60th of a second on the VIP: instructions until the cycles left after
+    // the display are spent, or a sprite is drawn. The interpreter waits for
+    // the display interrupt before drawing, so the sprite goes out at the
+    // start of the next frame and takes its time from that one.
+    pub fn run_vip_frame(&mut self, keys: [bool; 16]) -> TimedFrame {
+        let mut left = (FRAME_CYCLES - DISPLAY_CYCLES) as i64 - self.cycle_debt as i64;
+        let mut instructions = 0;
+        let mut finished = false;
+        // cycles() looks at the keys this frame is run with
+        self.keys = keys;
+        while left > 0 {
+            let instr = self.decode();
+            let cycles = self.cycles(instr) as i64;
+            instructions += 1;
+            if self.step(keys).finished {
+                finished = true;
+                break;
+            }
+            if let Draw {..} = instr {
+                left = left.min(0) - cycles;
+                break;
+            }
+            left -= cycles;
+        }
+        self.cycle_debt = (-left).max(0) as u32;
+        TimedFrame { instructions, finished }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use cpu::timing::{DISPLAY_CYCLES, FETCH_CYCLES, FRAME_CYCLES};
+    use instruction::Instruction::*;
+
+    #[test]
+    fn test_cycles() {
+        let mut cpu = Cpu::new();
+        cpu.registers[1] = 5;
+        assert_eq!(cpu.cycles(ConstantSe {reg: 1, byte: 5}), FETCH_CYCLES + 14);
+        assert_eq!(cpu.cycles(ConstantSe {reg: 1, byte: 6}), FETCH_CYCLES + 10);
+        assert_eq!(cpu.cycles(Draw {x: 0, y: 0, n: 2}), FETCH_CYCLES + 26 + 2 * 46);
+        assert_eq!(cpu.cycles(Draw {x: 1, y: 0, n: 2}), FETCH_CYCLES + 26 + 2 * 68);
+        cpu.registers[2] = 123;
+        assert_eq!(cpu.cycles(LdB {reg: 2}), FETCH_CYCLES + 80 + 16 * 6);
+    }
+
+    #[test]
+    fn test_vip_frame() {
+        let mut cpu = Cpu::new();
+        // V0 += 1; jump back
+        cpu.load_rom(vec![0x70, 0x01, 0x12, 0x00]);
+        let loop_cycles = 2 * FETCH_CYCLES + 10 + 12;
+        let budget = FRAME_CYCLES - DISPLAY_CYCLES;
+        let frame = cpu.run_vip_frame([false; 16]);
+        assert_eq!(frame.instructions, 2 * budget.div_ceil(loop_cycles));
+        assert!(!frame.finished);
+
+        // a sprite ends the frame, and its cycles come out of the next one
+        let mut cpu = Cpu::new();
+        cpu.load_rom(vec![0xD0, 0x05, 0x12, 0x00]);
+        assert_eq!(cpu.run_vip_frame([false; 16]).instructions, 1);
+        assert_eq!(cpu.cycle_debt, FETCH_CYCLES + 26 + 5 * 46);
+    }
+
+    #[test]
+    fn test_vip_frame_skips_on_held_keys() {
+        let mut cpu = Cpu::new();
+        // skip if key V0 is down, which it always is, to a jump back
+        cpu.load_rom(vec![0xE0, 0x9E, 0x12, 0x00, 0x12, 0x00]);
+        let loop_cycles = 2 * FETCH_CYCLES + 14 + 4 + 12;
+        let budget = FRAME_CYCLES - DISPLAY_CYCLES;
+        let mut keys = [false; 16];
+        keys[0] = true;
+        cpu.run_vip_frame(keys);
+        // every skip is counted as taken, the very first one too
+        assert_eq!(cpu.cycle_debt, loop_cycles * budget.div_ceil(loop_cycles) - budget);
+    }
+}
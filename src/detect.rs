@@ -0,0 +1,188 @@
+use instruction::{convert_platform_op, Instruction};
+use instruction::Instruction::*;
+use platform::Platform;
+
+// A guess at which platform a ROM was written for, made from the opcodes
+// only one of them has. Used for ROMs the game database doesn't know.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detection {
+    // None when nothing platform specific turned up
+    pub platform: Option<Platform>,
+    // the share of the evidence pointing at `platform`, from 0 to 1
+    pub confidence: f32,
+    // the opcodes that gave it away, in the order they were found
+    pub clues: Vec<&'static str>,
+}
+
+impl Detection {
+    // "Looks like schip (80% sure: 00FF, Dxy0)"
+    pub fn describe(&self) -> String {
+        match self.platform {
+            Some(platform) => format!("Looks like {} ({:.0}% sure: {})",
+                                      platform.name(), self.confidence * 100.0, self.clues.join(", ")),
+            None => String::from("No platform specific opcodes found")
+        }
+    }
+}
+
+// Which platform an instruction gives away, how strongly, and its pattern.
+// 0nnn is weak evidence, a stray machine code call is easy to land on.
+fn clue(instruction: &Instruction) -> Option<(Platform, u32, &'static str)> {
+    let found = match *instruction {
+        Sys {location} if location != 0 => (Platform::Chip8, 1, "0nnn"),
+        HighRes => (Platform::Schip, 3, "00FF"),
+        LowRes => (Platform::Schip, 3, "00FE"),
+        Exit => (Platform::Schip, 3, "00FD"),
+        ScrollLeft => (Platform::Schip, 2, "00FC"),
+        ScrollRight => (Platform::Schip, 2, "00FB"),
+        ScrollDown {..} => (Platform::Schip, 2, "00Cn"),
+        Draw {n: 0, ..} => (Platform::Schip, 2, "Dxy0"),
+        BigFont {..} => (Platform::Schip, 2, "Fx30"),
+        SaveFlags {..} => (Platform::Schip, 2, "Fx75"),
+        LoadFlags {..} => (Platform::Schip, 2, "Fx85"),
+        LdILong => (Platform::XoChip, 3, "F000"),
+        Plane {..} => (Platform::XoChip, 3, "Fn01"),
+        Audio => (Platform::XoChip, 2, "F002"),
+        Pitch {..} => (Platform::XoChip, 2, "Fx3A"),
+        ScrollUp {..} => (Platform::XoChip, 2, "00Dn"),
+        SaveRange {..} => (Platform::XoChip, 2, "5xy2"),
+        LoadRange {..} => (Platform::XoChip, 2, "5xy3"),
+        _ => return None
+    };
+    Some(found)
+}
+
+// The opcode at `address` of a ROM loaded at `start`
+fn word(rom: &[u8], start: usize, address: usize) -> Option<u16> {
+    let at = address.checked_sub(start)?;
+    if at + 1 < rom.len() {
+        Some((rom[at] as u16) << 8 | rom[at + 1] as u16)
+    } else {
+        None
+    }
+}
+
+// Follows the program from its entry point instead of decoding every word,
+// since sprites and other data decode to opcodes too. Paths stop at anything
+// that doesn't decode, and at computed jumps that can't be followed.
+// Opcodes are read the way `platform` reads them, and the ROM is loaded
+// where it loads them, when it's already known.
+pub fn detect(rom: &[u8], platform: Option<Platform>) -> Detection {
+    let start = platform.map(Platform::profile).unwrap_or_default().load_address as usize;
+    let mut seen = vec![false; rom.len()];
+    let mut pending = vec![start];
+    // indexed like Platform: Chip8, Schip, XoChip
+    let mut scores = [0u32; 3];
+    let mut clues = Vec::new();
+
+    while let Some(pc) = pending.pop() {
+        let op = match word(rom, start, pc) {
+            Some(op) if !seen[pc - start] => op,
+            _ => continue
+        };
+        seen[pc - start] = true;
+
+        let instruction = convert_platform_op(op, platform);
+        if let Some((platform, weight, pattern)) = clue(&instruction) {
+            scores[platform as usize] += weight;
+            if !clues.contains(&pattern) {
+                clues.push(pattern);
+            }
+        }
+
+        match instruction {
+            Jmp {location} => pending.push(location as usize),
+            Call {location} => pending.extend_from_slice(&[location as usize, pc + 2]),
+            Ret | Exit | JmpA {..} | Sys {location: 0} | Unknown => (),
+            Se {..} | Sne {..} | ConstantSe {..} | ConstantSne {..} | Skp {..} | Sknp {..} => {
+                // a skip hops over both words of F000 nnnn and 01nn nnnn
+                let skipped = word(rom, start, pc + 2).map_or(2, |next| convert_platform_op(next, platform).length());
+                pending.extend_from_slice(&[pc + 2, pc + 2 + skipped]);
+            }
+            // past the address of F000 nnnn and 01nn nnnn too
+            _ => pending.push(pc + instruction.length())
+        }
+    }
+
+    let total: u32 = scores.iter().sum();
+    // XO-CHIP has all of SUPER-CHIP's opcodes, so once anything points at
+    // XO-CHIP the SUPER-CHIP ones do too
+    if scores[Platform::XoChip as usize] > 0 {
+        scores[Platform::XoChip as usize] += scores[Platform::Schip as usize];
+    }
+    let best = [Platform::Chip8, Platform::Schip, Platform::XoChip].iter().cloned()
+        .max_by_key(|&platform| scores[platform as usize])
+        .filter(|_| total > 0);
+
+    Detection {
+        platform: best,
+        confidence: best.map_or(0.0, |platform| scores[platform as usize] as f32 / total as f32),
+        clues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use detect::detect;
+    use platform::Platform;
+
+    #[test]
+    fn test_plain_chip8() {
+        let detection = detect(&[0x00, 0xE0, 0x60, 0x01, 0x12, 0x02], None);
+        assert_eq!(detection.platform, None);
+        assert_eq!(detection.describe(), "No platform specific opcodes found");
+    }
+
+    #[test]
+    fn test_only_reachable_code_counts() {
+        let rom = [
+            0x00, 0xFF, // hires
+            0xD0, 0x10, // 16x16 sprite
+            0x12, 0x04, // loop forever
+            0x00, 0xC1, 0xF0, 0x00, // sprite data that looks like XO-CHIP
+        ];
+        let detection = detect(&rom, None);
+        assert_eq!(detection.platform, Some(Platform::Schip));
+        assert_eq!(detection.confidence, 1.0);
+        assert_eq!(detection.describe(), "Looks like schip (100% sure: 00FF, Dxy0)");
+    }
+
+    #[test]
+    fn test_xochip_wins_over_schip() {
+        let rom = [
+            0x00, 0xFF, // hires
+            0xF0, 0x00, 0x02, 0x10, // I = 0x210
+            0xF2, 0x01, // plane 2
+            0x01, 0x23, // machine code call
+        ];
+        let detection = detect(&rom, None);
+        assert_eq!(detection.platform, Some(Platform::XoChip));
+        assert_eq!(detection.clues, vec!["00FF", "F000", "Fn01", "0nnn"]);
+        // 9 of the 10 points, the machine code call being the odd one out
+        assert_eq!(detection.confidence, 0.9);
+    }
+
+    #[test]
+    fn test_megachip_long_address() {
+        let rom = [
+            0x30, 0x00, // skip the next instruction if V0 == 0
+            0x01, 0x00, 0x00, 0xFF, // I = 0x0000FF, whose address isn't hires
+            0x12, 0x06, // loop forever
+        ];
+        assert_eq!(detect(&rom, Some(Platform::MegaChip)).platform, None);
+        assert_eq!(detect(&rom, None).platform, Some(Platform::Schip));
+    }
+
+    #[test]
+    fn test_load_address() {
+        let rom = [
+            0x16, 0x04, // jump to hires, when loaded at 0x600
+            0x12, 0x02, // loop forever
+            0x00, 0xFF, // hires
+            0x16, 0x06, // loop forever
+        ];
+        assert_eq!(detect(&rom, Some(Platform::Eti660)).clues, vec!["00FF"]);
+        // loaded at 0x200 the jump leads out of the ROM
+        assert_eq!(detect(&rom, None).platform, None);
+    }
+}
@@ -0,0 +1,414 @@
+use std::ops::{Index, IndexMut, Range};
+
+use palette::{Rgb, CHIP8X_COLORS};
+use std::slice::Chunks;
+
+use CHIP8_HEIGHT;
+use CHIP8_WIDTH;
+
+// CHIP-8X's colors, from the VP-590 color board: one background color for
+// the whole screen, and a foreground color for lit pixels in each strip 8
+// pixels wide and 1 high. Colors are 0 to 7, see palette::CHIP8X_COLORS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorAttributes {
+    background: usize,
+    columns: usize,
+    foreground: Vec<u8>,
+}
+
+// The background colors 02A0 steps through, blue first
+const BACKGROUNDS: [u8; 4] = [2, 0, 4, 1];
+
+// Pixels across a color strip
+pub const COLOR_COLUMN_WIDTH: usize = 8;
+
+impl ColorAttributes {
+    // A blue background with everything drawn in red, as the board starts up
+    pub fn new(width: usize, height: usize) -> ColorAttributes {
+        let columns = width.div_ceil(COLOR_COLUMN_WIDTH);
+        ColorAttributes { background: 0, columns, foreground: vec![1; columns * height] }
+    }
+
+    pub fn background(&self) -> u8 {
+        BACKGROUNDS[self.background]
+    }
+
+    pub fn next_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len();
+    }
+
+    // The color of a lit pixel at x, y
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        self.foreground.get(y * self.columns + x / COLOR_COLUMN_WIDTH).cloned().unwrap_or(1)
+    }
+
+    // Colors the strips in columns and rows, leaving out any past the edge
+    pub fn fill(&mut self, columns: Range<usize>, rows: Range<usize>, color: u8) {
+        let height = self.foreground.len() / self.columns;
+        for row in rows.start.min(height)..rows.end.min(height) {
+            for column in columns.start.min(self.columns)..columns.end.min(self.columns) {
+                self.foreground[row * self.columns + column] = color & 7;
+            }
+        }
+    }
+}
+
+// MegaChip's picture in full color, row after row, which sprites are blended
+// into. The screen's alpha fades all of it towards black.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrueColor {
+    pub pixels: Vec<Rgb>,
+    pub alpha: u8,
+}
+
+// A picture of the screen, one byte a pixel, row after row. Platforms
+// disagree on its size, so it's only known at run time; screen[y][x] works
+// as it would on an array.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    // CHIP-8X's colors on top of the pixels, None everywhere else
+    colors: Option<ColorAttributes>,
+    // MegaChip's colors, with the pixels holding the palette index
+    true_color: Option<TrueColor>,
+}
+
+impl Default for Screen {
+    fn default() -> Screen {
+        Screen::new(CHIP8_WIDTH, CHIP8_HEIGHT)
+    }
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Screen {
+        Screen { width, height, pixels: vec![0; width * height], colors: None, true_color: None }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn rows(&self) -> Chunks<'_, u8> {
+        self.pixels.chunks(self.width)
+    }
+
+    // Every pixel, row after row
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn colors(&self) -> Option<&ColorAttributes> {
+        self.colors.as_ref()
+    }
+
+    pub fn true_color(&self) -> Option<&TrueColor> {
+        self.true_color.as_ref()
+    }
+
+    // One color per pixel, row by row, for screens that bring their own:
+    // CHIP-8X's color layer or MegaChip's true color. None for the rest.
+    pub fn own_colors(&self) -> Option<Vec<Rgb>> {
+        match (&self.colors, &self.true_color) {
+            (Some(attributes), _) => Some(attribute_colors(self, attributes)),
+            (_, Some(true_color)) => Some(faded(true_color)),
+            _ => None
+        }
+    }
+
+    // One color per pixel, row by row: the screen's own, or failing that
+    // each pixel looked up in colors, values past the end using the last
+    pub fn rgb(&self, colors: &[Rgb]) -> Vec<Rgb> {
+        self.own_colors().unwrap_or_else(|| {
+            self.pixels.iter().map(|&pixel| colors[(pixel as usize).min(colors.len() - 1)]).collect()
+        })
+    }
+
+    // 0 outside the screen
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        if x < self.width && y < self.height { self.pixels[y * self.width + x] } else { 0 }
+    }
+}
+
+impl Index<usize> for Screen {
+    type Output = [u8];
+
+    fn index(&self, y: usize) -> &[u8] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+}
+
+impl IndexMut<usize> for Screen {
+    fn index_mut(&mut self, y: usize) -> &mut [u8] {
+        &mut self.pixels[y * self.width..(y + 1) * self.width]
+    }
+}
+
+// The screen the CPU draws into. Frontends read it by reference once a
+// frame, and can ask whether it changed since they last looked.
+pub struct Framebuffer {
+    pixels: Screen,
+    dirty: bool,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new(CHIP8_WIDTH, CHIP8_HEIGHT)
+    }
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        // a new screen, of whatever size, has to be shown once
+        Framebuffer { pixels: Screen::new(width, height), dirty: true }
+    }
+
+    pub fn pixels(&self) -> &Screen {
+        &self.pixels
+    }
+
+    pub fn width(&self) -> usize {
+        self.pixels.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.pixels.height
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in &mut self.pixels.pixels {
+            *pixel = 0;
+        }
+        if let Some(ref mut true_color) = self.pixels.true_color {
+            for pixel in &mut true_color.pixels {
+                *pixel = (0, 0, 0);
+            }
+        }
+        self.dirty = true;
+    }
+
+    // XORs a pixel in, true if that turned a lit pixel off
+    pub fn toggle(&mut self, x: usize, y: usize, color: u8) -> bool {
+        if color == 0 {
+            return false;
+        }
+        let collision = self.pixels[y][x] != 0;
+        self.pixels[y][x] ^= color;
+        self.dirty = true;
+        collision
+    }
+
+    // Turns CHIP-8X's color layer on, in its starting colors
+    pub fn enable_colors(&mut self) {
+        self.pixels.colors = Some(ColorAttributes::new(self.pixels.width, self.pixels.height));
+    }
+
+    // The color layer to change, if there is one. The whole screen is redrawn
+    // after, a color change shows even where nothing was drawn.
+    pub fn colors_mut(&mut self) -> Option<&mut ColorAttributes> {
+        if self.pixels.colors.is_some() {
+            self.dirty = true;
+        }
+        self.pixels.colors.as_mut()
+    }
+
+    // Turns MegaChip's full color picture on, black and fully shown
+    pub fn enable_true_color(&mut self) {
+        let pixels = vec![(0, 0, 0); self.pixels.pixels.len()];
+        self.pixels.true_color = Some(TrueColor { pixels, alpha: 0xFF });
+    }
+
+    // Sets a pixel's palette index and color, true if it covered a lit one
+    pub fn paint(&mut self, x: usize, y: usize, index: u8, color: Rgb) -> bool {
+        let collision = self.pixels[y][x] != 0;
+        self.pixels[y][x] = index;
+        let width = self.pixels.width;
+        if let Some(ref mut true_color) = self.pixels.true_color {
+            true_color.pixels[y * width + x] = color;
+        }
+        self.dirty = true;
+        collision
+    }
+
+    pub fn set_alpha(&mut self, alpha: u8) {
+        if let Some(ref mut true_color) = self.pixels.true_color {
+            true_color.alpha = alpha;
+        }
+        self.dirty = true;
+    }
+
+    // Moves the picture dx pixels right and dy down, what it uncovers is blank
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.pixels.width, self.pixels.height);
+        self.pixels.pixels = shifted(&self.pixels.pixels, width, height, dx, dy, 0);
+        if let Some(ref mut true_color) = self.pixels.true_color {
+            true_color.pixels = shifted(&true_color.pixels, width, height, dx, dy, (0, 0, 0));
+        }
+        self.dirty = true;
+    }
+
+    // Whether anything was drawn since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        ::std::mem::replace(&mut self.dirty, false)
+    }
+}
+
+// Rows of pixels moved dx right and dy down, with blank where nothing moved in
+fn shifted<T: Copy>(pixels: &[T], width: usize, height: usize, dx: isize, dy: isize, blank: T) -> Vec<T> {
+    let (width, height) = (width as isize, height as isize);
+    (0..height).flat_map(|y| (0..width).map(move |x| {
+        let (from_x, from_y) = (x - dx, y - dy);
+        if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+            pixels[(from_y * width + from_x) as usize]
+        } else {
+            blank
+        }
+    })).collect()
+}
+
+// One color per pixel, row by row, from CHIP-8X's color layer
+fn attribute_colors(screen: &Screen, attributes: &ColorAttributes) -> Vec<Rgb> {
+    let background = CHIP8X_COLORS[attributes.background() as usize];
+    screen.rows().enumerate().flat_map(|(y, row)| {
+        row.iter().enumerate().map(move |(x, &pixel)| {
+            if pixel != 0 { CHIP8X_COLORS[attributes.foreground(x, y) as usize] } else { background }
+        })
+    }).collect()
+}
+
+// MegaChip's picture, faded towards black by the screen's alpha
+fn faded(true_color: &TrueColor) -> Vec<Rgb> {
+    let alpha = true_color.alpha as u32;
+    let fade = |channel: u8| (channel as u32 * alpha / 255) as u8;
+    true_color.pixels.iter().map(|&(r, g, b)| (fade(r), fade(g), fade(b))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use framebuffer::{attribute_colors, faded, ColorAttributes, Framebuffer, Screen, TrueColor};
+    use palette::CHIP8X_COLORS;
+
+    #[test]
+    fn test_toggle_collides() {
+        let mut framebuffer = Framebuffer::default();
+        assert!(!framebuffer.toggle(3, 4, 1));
+        assert_eq!(framebuffer.pixels()[4][3], 1);
+        assert!(framebuffer.toggle(3, 4, 1));
+        assert_eq!(framebuffer.pixels()[4][3], 0);
+    }
+
+    #[test]
+    fn test_dirty_until_taken() {
+        let mut framebuffer = Framebuffer::default();
+        assert!(framebuffer.take_dirty());
+        assert!(!framebuffer.take_dirty());
+
+        // drawing nothing doesn't count
+        framebuffer.toggle(60, 30, 0);
+        assert!(!framebuffer.take_dirty());
+        framebuffer.toggle(3, 4, 1);
+        framebuffer.toggle(10, 2, 1);
+        assert!(framebuffer.take_dirty());
+        assert!(!framebuffer.take_dirty());
+
+        framebuffer.clear();
+        assert!(framebuffer.take_dirty());
+    }
+
+    #[test]
+    fn test_screen_rows() {
+        let mut screen = Screen::new(64, 48);
+        screen[47][63] = 1;
+        assert_eq!(screen.rows().count(), 48);
+        assert_eq!(screen.rows().last().unwrap()[63], 1);
+        assert_eq!(screen.get(63, 47), 1);
+        assert_eq!(screen.get(64, 47), 0);
+    }
+
+    #[test]
+    fn test_color_attributes() {
+        let mut colors = ColorAttributes::new(64, 32);
+        assert_eq!((colors.background(), colors.foreground(10, 10)), (2, 1));
+        colors.fill(1..3, 4..8, 5);
+        assert_eq!(colors.foreground(8, 4), 5);
+        assert_eq!(colors.foreground(23, 7), 5);
+        assert_eq!(colors.foreground(24, 7), 1);
+        assert_eq!(colors.foreground(8, 8), 1);
+        // off the edge is left out
+        colors.fill(7..12, 30..40, 4);
+        assert_eq!(colors.foreground(63, 31), 4);
+
+        for _ in 0..4 {
+            colors.next_background();
+        }
+        assert_eq!(colors.background(), 2);
+
+        // clearing the screen keeps the colors
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.enable_colors();
+        framebuffer.colors_mut().unwrap().fill(0..1, 0..1, 3);
+        framebuffer.clear();
+        assert_eq!(framebuffer.pixels().colors().map(|colors| colors.foreground(0, 0)), Some(3));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut framebuffer = Framebuffer::new(8, 4);
+        framebuffer.toggle(1, 1, 1);
+        framebuffer.toggle(7, 3, 1);
+        framebuffer.take_dirty();
+        framebuffer.scroll(4, 2);
+        assert!(framebuffer.take_dirty());
+        assert_eq!(framebuffer.pixels()[3][5], 1);
+        // the one in the corner went off the edge
+        assert_eq!(framebuffer.pixels().pixels().iter().filter(|&&pixel| pixel != 0).count(), 1);
+        framebuffer.scroll(-4, -1);
+        assert_eq!(framebuffer.pixels()[2][1], 1);
+    }
+
+    #[test]
+    fn test_paint_true_color() {
+        let mut framebuffer = Framebuffer::new(256, 192);
+        framebuffer.enable_true_color();
+        assert!(!framebuffer.paint(255, 191, 7, (1, 2, 3)));
+        assert!(framebuffer.paint(255, 191, 8, (4, 5, 6)));
+        assert_eq!(framebuffer.pixels()[191][255], 8);
+        assert_eq!(framebuffer.pixels().true_color().unwrap().pixels[256 * 192 - 1], (4, 5, 6));
+
+        framebuffer.clear();
+        assert_eq!(framebuffer.pixels().true_color().unwrap().pixels[256 * 192 - 1], (0, 0, 0));
+    }
+
+    #[test]
+    fn test_attribute_colors() {
+        let mut screen = Screen::new(16, 2);
+        screen[0][3] = 1;
+        screen[1][9] = 1;
+        let mut attributes = ColorAttributes::new(16, 2);
+        attributes.fill(1..2, 1..2, 6);
+        let colors = attribute_colors(&screen, &attributes);
+        assert_eq!(colors[0], CHIP8X_COLORS[2]);
+        assert_eq!(colors[3], CHIP8X_COLORS[1]);
+        assert_eq!(colors[16 + 9], CHIP8X_COLORS[6]);
+    }
+
+    #[test]
+    fn test_faded() {
+        let true_color = TrueColor { pixels: vec![(255, 100, 0)], alpha: 0x80 };
+        assert_eq!(faded(&true_color), vec![(128, 50, 0)]);
+    }
+
+    #[test]
+    fn test_rgb() {
+        let mut screen = Screen::new(4, 1);
+        screen[0][1] = 1;
+        screen[0][2] = 7;
+        assert_eq!(screen.rgb(&[(0, 0, 0), (1, 1, 1), (2, 2, 2)]), vec![(0, 0, 0), (1, 1, 1), (2, 2, 2), (0, 0, 0)]);
+        assert_eq!(screen.own_colors(), None);
+    }
+}
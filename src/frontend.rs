@@ -0,0 +1,169 @@
+use framebuffer::Screen;
+use palette::Palette;
+use CHIP8_WIDTH;
+
+// Emulator-level actions bound to host keys, as opposed to the hex keypad.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Hotkey {
+    Screenshot,
+    // start or stop recording to an animated GIF
+    RecordGif,
+    // start or stop recording to numbered PNGs
+    RecordFrames,
+    // switch to the next color palette
+    NextPalette,
+    Fullscreen,
+    // stop and restart the emulation
+    Pause,
+    // show or hide the FPS line
+    ToggleOsd,
+}
+
+// How the emulator is keeping up, updated about once a second.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    // 60 Hz frames emulated per second
+    pub fps: f64,
+    // instructions per second
+    pub ips: f64,
+    pub paused: bool,
+}
+
+// How big to draw the pixels of a screen `width` across so it comes out as
+// wide as CHIP-8's 64 pixels at `scale`. Bigger screens get smaller pixels
+// rather than pictures too big to build every frame.
+pub fn scale_for(scale: u32, width: usize) -> u32 {
+    (scale as usize * CHIP8_WIDTH / width.max(1)).max(1) as u32
+}
+
+// What main needs from a frontend: somewhere to show the screen...
+pub trait Renderer {
+    fn draw(&mut self, screen: &Screen);
+
+    // How big a CHIP-8 pixel is on the host, screenshots are saved to match
+    fn scale(&self) -> u32 {
+        1
+    }
+
+    fn palette(&self) -> Palette {
+        Palette::default()
+    }
+
+    fn set_palette(&mut self, _palette: Palette) {}
+
+    // True if draw should be called again even though the screen hasn't
+    // changed, e.g. while pixels fade out or after the window was resized
+    fn fading(&self) -> bool {
+        false
+    }
+
+    fn toggle_fullscreen(&mut self) {}
+
+    // Tell the player something, by default on stdout
+    fn message(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn set_status(&mut self, _status: Status) {}
+
+    fn toggle_osd(&mut self) {}
+
+    // The keys held this frame, for frontends that show them
+    fn set_keys(&mut self, _keys: [bool; 16]) {}
+
+    // The hex key drawn at a point in the window, if there is one
+    fn keypad_at(&self, _x: i32, _y: i32) -> Option<u8> {
+        None
+    }
+}
+
+// ...and somewhere to read the hex keypad from. None means the user quit.
+pub trait Input {
+    fn get_keys(&mut self) -> Option<[bool; 16]>;
+
+    // Hotkeys pressed since the last call
+    fn hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+
+    // Where the left mouse button is held down, in window coordinates
+    fn pointer(&self) -> Option<(i32, i32)> {
+        None
+    }
+}
+
+// A window and a keyboard are separate objects in SDL, a terminal is both.
+// Pairing them up lets main treat either the same way.
+impl<R: Renderer, I> Renderer for (R, I) {
+    fn draw(&mut self, screen: &Screen) {
+        self.0.draw(screen)
+    }
+
+    fn scale(&self) -> u32 {
+        self.0.scale()
+    }
+
+    fn palette(&self) -> Palette {
+        self.0.palette()
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.0.set_palette(palette)
+    }
+
+    fn fading(&self) -> bool {
+        self.0.fading()
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        self.0.toggle_fullscreen()
+    }
+
+    fn message(&mut self, text: &str) {
+        self.0.message(text)
+    }
+
+    fn set_status(&mut self, status: Status) {
+        self.0.set_status(status)
+    }
+
+    fn toggle_osd(&mut self) {
+        self.0.toggle_osd()
+    }
+
+    fn set_keys(&mut self, keys: [bool; 16]) {
+        self.0.set_keys(keys)
+    }
+
+    fn keypad_at(&self, x: i32, y: i32) -> Option<u8> {
+        self.0.keypad_at(x, y)
+    }
+}
+
+impl<R, I: Input> Input for (R, I) {
+    fn get_keys(&mut self) -> Option<[bool; 16]> {
+        self.1.get_keys()
+    }
+
+    fn hotkeys(&mut self) -> Vec<Hotkey> {
+        self.1.hotkeys()
+    }
+
+    fn pointer(&self) -> Option<(i32, i32)> {
+        self.1.pointer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::scale_for;
+
+    #[test]
+    fn test_scale_for() {
+        assert_eq!(scale_for(20, 64), 20);
+        // SUPER-CHIP's and MegaChip's screens come out 1280 pixels wide too
+        assert_eq!(scale_for(20, 128), 10);
+        assert_eq!(scale_for(20, 256), 5);
+        assert_eq!(scale_for(1, 256), 1);
+    }
+}
@@ -0,0 +1,166 @@
+use toml::Value;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use palette::Palette;
+use platform::{Platform, Quirks};
+use sha1;
+
+// What the database knows about one ROM. Anything left out falls back to
+// the command line, the bindings file or the defaults.
+#[derive(Clone, Debug, Default)]
+pub struct GameInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    // a table in the same format as [keys] in the bindings file
+    pub keys: Option<Value>,
+    pub palette: Option<Palette>,
+}
+
+impl GameInfo {
+    fn parse(table: &Value) -> Result<GameInfo, String> {
+        let string = |name: &str| -> Result<Option<String>, String> {
+            match table.get(name) {
+                Some(value) => value.as_str().map(|s| Some(String::from(s))).ok_or(format!("{} must be a string", name)),
+                None => Ok(None)
+            }
+        };
+
+        let instructions_per_frame = match table.get("ipf") {
+            Some(ipf) => Some(ipf.as_integer()
+                .filter(|&ipf| ipf > 0 && ipf <= i64::from(u32::MAX))
+                .ok_or("ipf must be a positive number")? as u32),
+            None => None
+        };
+
+        Ok(GameInfo {
+            title: string("title")?,
+            author: string("author")?,
+            platform: string("platform")?.map(|name| Platform::parse(&name)).transpose()?,
+            quirks: string("quirks")?.map(|name| Quirks::preset(&name)).transpose()?,
+            instructions_per_frame,
+            keys: table.get("keys").cloned(),
+            palette: table.get("palette").map(Palette::from_value).transpose()?,
+        })
+    }
+
+    // The preset if one was given, otherwise whatever the platform uses
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.quirks.or_else(|| self.platform.map(Platform::quirks))
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = Some(quirks);
+    }
+
+    // "Brix by Andreas Gustafsson"
+    pub fn describe(&self) -> Option<String> {
+        match (&self.title, &self.author) {
+            (Some(title), Some(author)) => Some(format!("{} by {}", title, author)),
+            (Some(title), None) => Some(title.clone()),
+            _ => None
+        }
+    }
+}
+
+// Games by the SHA-1 of their ROM.
+#[derive(Clone, Debug, Default)]
+pub struct GameDb {
+    games: HashMap<String, GameInfo>,
+}
+
+impl GameDb {
+    // The games.toml that ships with the emulator, parsed the first time
+    // it's asked for
+    pub fn builtin() -> GameDb {
+        static BUILTIN: OnceLock<GameDb> = OnceLock::new();
+        BUILTIN.get_or_init(|| GameDb::parse(include_str!("../games.toml")).expect("The built in games.toml is broken"))
+            .clone()
+    }
+
+    pub fn load(path: &Path) -> Result<GameDb, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        GameDb::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<GameDb, String> {
+        let config = text.parse::<Value>().map_err(|e| e.to_string())?;
+        let table = config.as_table().ok_or("The game database must be a table")?;
+
+        let mut games = HashMap::new();
+        for (hash, entry) in table {
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("{} is not a SHA-1", hash));
+            }
+            let info = GameInfo::parse(entry).map_err(|e| format!("{}: {}", hash, e))?;
+            games.insert(hash.to_ascii_lowercase(), info);
+        }
+        Ok(GameDb { games })
+    }
+
+    // Entries in `other` replace ones for the same ROM
+    pub fn extend(&mut self, other: GameDb) {
+        self.games.extend(other.games);
+    }
+
+    // Adds or replaces the entry for one ROM
+    pub fn insert(&mut self, rom: &[u8], game: GameInfo) {
+        self.games.insert(sha1::hex_digest(rom), game);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&GameInfo> {
+        self.games.get(&sha1::hex_digest(rom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gamedb::GameDb;
+    use platform::Platform;
+
+    // "abc"
+    const ROM: &[u8] = b"abc";
+    const HASH: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+
+    #[test]
+    fn test_builtin_parses() {
+        GameDb::builtin();
+    }
+
+    #[test]
+    fn test_lookup() {
+        let text = format!("[{}]\ntitle = \"Test\"\nauthor = \"Someone\"\nplatform = \"schip\"\nipf = 30\npalette = \"amber\"\n\
+                            [{}.keys]\n4 = \"Left\"\n", HASH.to_uppercase(), HASH.to_uppercase());
+        let db = GameDb::parse(&text).unwrap();
+        let game = db.lookup(ROM).unwrap();
+        assert_eq!(game.describe(), Some(String::from("Test by Someone")));
+        assert_eq!(game.quirks(), Some(Platform::Schip.quirks()));
+        assert_eq!(game.instructions_per_frame, Some(30));
+        assert_eq!(game.palette.as_ref().map(|palette| palette.name.as_str()), Some("amber"));
+        assert!(game.keys.is_some());
+        assert!(db.lookup(b"abd").is_none());
+    }
+
+    #[test]
+    fn test_quirk_preset_beats_platform() {
+        let db = GameDb::parse(&format!("[{}]\nplatform = \"schip\"\nquirks = \"chip8\"\n", HASH)).unwrap();
+        assert_eq!(db.lookup(ROM).unwrap().quirks(), Some(Platform::Chip8.quirks()));
+    }
+
+    #[test]
+    fn test_bad_entries() {
+        assert!(GameDb::parse("[brix]\ntitle = \"Brix\"").is_err());
+        assert!(GameDb::parse(&format!("[{}]\nplatform = \"gameboy\"\n", HASH)).is_err());
+        assert!(GameDb::parse(&format!("[{}]\nipf = 0\n", HASH)).is_err());
+    }
+}
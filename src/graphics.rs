@@ -1,8 +1,20 @@
-use sdl2::pixels;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+pub mod keypad;
+pub mod osd;
+pub mod persistence;
+pub mod scaler;
+
+use sdl2::pixels::{self, PixelFormatEnum};
 use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
 
+use framebuffer::Screen;
+use frontend::{self, Renderer, Status};
+use palette::{Palette, Rgb};
+use self::keypad::Area;
+use self::osd::{Osd, GLYPH_HEIGHT, GLYPH_WIDTH};
+use self::persistence::{Filter, Persistence};
+use self::scaler::ScaleOptions;
 use CHIP8_WIDTH;
 use CHIP8_HEIGHT;
 
@@ -11,12 +23,26 @@ const HEIGHT: u32 = (CHIP8_HEIGHT as u32) * SCALE;
 const WIDTH: u32 = (CHIP8_WIDTH as u32) * SCALE;
 
 pub struct Graphics {
-    screen: Canvas<Window>
+    screen: Canvas<Window>,
+    // the scaled picture is drawn here on the CPU, then stretched to the window
+    texture: Texture,
+    texture_size: (u32, u32),
+    // window size at the last draw, to notice it being resized
+    window_size: (u32, u32),
+    palette: Palette,
+    filter: Filter,
+    scaling: ScaleOptions,
+    osd: Osd,
+    // whether the hex keypad panel is shown next to the game
+    keypad: bool,
+    keys: [bool; 16],
+    // set when something besides the game changed and wants drawing
+    redraw: bool
 }
 
 impl Graphics {
 
-    pub fn new(sdl_context: &sdl2::Sdl) -> Graphics {
+    pub fn new(sdl_context: &sdl2::Sdl, palette: Palette, persistence: Persistence, scaling: ScaleOptions) -> Graphics {
         let video_sub = sdl_context.video().unwrap();
         let window = video_sub
             .window(
@@ -25,36 +51,241 @@ impl Graphics {
                 HEIGHT
             )
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .unwrap();
         let mut screen = window.into_canvas().build().unwrap();
 
-        screen.set_draw_color(pixels::Color::RGB(0, 0, 0));
+        let (r, g, b) = palette.color(0);
+        screen.set_draw_color(pixels::Color::RGB(r, g, b));
         screen.clear();
         screen.present();
 
-        Graphics { screen }
+        let texture = screen.texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH, HEIGHT)
+            .unwrap();
+
+        Graphics {
+            screen,
+            texture,
+            texture_size: (WIDTH, HEIGHT),
+            window_size: (WIDTH, HEIGHT),
+            palette,
+            filter: Filter::new(persistence),
+            scaling,
+            osd: Osd::new(),
+            keypad: false,
+            keys: [false; 16],
+            redraw: false
+        }
 
     }
 
-    pub fn draw(&mut self, screen: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
-        for (y, row) in screen.iter().enumerate() {
-            for (x, &col) in row.iter().enumerate() {
-                let x = x as u32 * SCALE;
-                let y = y as u32 * SCALE;
+    pub fn draw(&mut self, screen: &Screen) {
+        // CHIP-8X and MegaChip bring their own colors, the palette and filter are left out
+        let colors = match screen.own_colors() {
+            Some(colors) => colors,
+            None => self.filter.apply(screen, &self.palette)
+        };
+        let mut image = picture(&colors, screen.width(), screen.height(), &self.scaling);
+        self.osd.draw(&mut image);
 
-                let mut color = pixels::Color::RGB(0, 0, 0);
-                if col == 1 {
-                    color = pixels::Color::RGB(0, 250, 0);
-                }
-                self.screen.set_draw_color(color);
+        // the picture changes size with the scaler, and with the screen's shape: 64x48 on
+        // the ETI-660, 256x192 once a MegaChip program turns its color screen on.
+        // SUPER-CHIP's 128x64 keeps the size and gets pixels half as big.
+        let size = (image.width as u32, image.height as u32);
+        if size != self.texture_size {
+            let texture = self.screen.texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGB24, size.0, size.1)
+                .unwrap();
+            // safe, the canvas that owns the old texture is still alive
+            unsafe { ::std::mem::replace(&mut self.texture, texture).destroy() };
+            self.texture_size = size;
+        }
 
-                let _ = self.screen.fill_rect(Rect::new(x as i32, y as i32, SCALE, SCALE));
+        self.window_size = self.screen.output_size().unwrap_or(self.window_size);
+        let (game, panel) = if self.keypad {
+            keypad::split(self.window_size)
+        } else {
+            ((0, 0, self.window_size.0, self.window_size.1), (0, 0, 0, 0))
+        };
+        let view = viewport((game.2, game.3), self.texture_size, self.scaling.integer);
+
+        let _ = self.texture.update(None, &image.pixels, image.width * 3);
+        self.screen.set_draw_color(pixels::Color::RGB(0, 0, 0));
+        self.screen.clear();
+        let _ = self.screen.copy(&self.texture, None, Rect::new(game.0 + view.0, game.1 + view.1, view.2, view.3));
+        if self.keypad {
+            self.draw_keypad(panel);
+        }
+        self.screen.present();
+        self.redraw = false;
+    }
+
+    // Buttons light up in the foreground color while their key is held
+    fn draw_keypad(&mut self, panel: Area) {
+        let (off, on) = (pixels::Color::RGB(0x30, 0x30, 0x30), self.palette.color(1));
+        let on = pixels::Color::RGB(on.0, on.1, on.2);
+
+        for (key, (x, y, width, height)) in keypad::keys(panel) {
+            let pressed = self.keys[key as usize];
+            self.screen.set_draw_color(if pressed { on } else { off });
+            let _ = self.screen.fill_rect(Rect::new(x, y, width, height));
+
+            // the hex digit in the middle, in whichever color the button isn't
+            let dot = (width / 10).max(1);
+            let left = x + width.saturating_sub(GLYPH_WIDTH as u32 * dot) as i32 / 2;
+            let top = y + height.saturating_sub(GLYPH_HEIGHT as u32 * dot) as i32 / 2;
+            let label = format!("{:X}", key).chars().next().unwrap_or('?');
+            self.screen.set_draw_color(if pressed { off } else { on });
+            for (row, bits) in osd::glyph(label).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> column) != 0 {
+                        let (dx, dy) = ((column as u32 * dot) as i32, (row as u32 * dot) as i32);
+                        let _ = self.screen.fill_rect(Rect::new(left + dx, top + dy, dot, dot));
+                    }
+                }
             }
         }
+    }
+
+    // Widens the window to make room for the keypad panel
+    pub fn show_keypad(&mut self) {
+        self.keypad = true;
+        let _ = self.screen.window_mut().set_size(WIDTH + HEIGHT, HEIGHT);
+        self.redraw = true;
+    }
+
+    // The key under a point in window coordinates, if the keypad is up
+    pub fn keypad_at(&self, x: i32, y: i32) -> Option<u8> {
+        if !self.keypad {
+            return None;
+        }
+        // mouse positions are in window units, which aren't pixels on high DPI screens
+        let (width, height) = self.screen.window().size();
+        let (x, y) = (x * self.window_size.0 as i32 / width.max(1) as i32,
+                      y * self.window_size.1 as i32 / height.max(1) as i32);
+        keypad::key_at(keypad::split(self.window_size).1, x, y)
+    }
 
-        self.screen.present();
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.screen.window_mut();
+        let next = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off
+        };
+        let _ = window.set_fullscreen(next);
+    }
+
+}
+
+// Where the picture goes in the window as (x, y, width, height): as big as
+// fits without changing its shape, centered, with black bars on the sides
+// that are left over. With `integer` only whole multiples are used, so every
+// pixel comes out the same size, unless the window is too small for even one.
+fn viewport(window: (u32, u32), picture: (u32, u32), integer: bool) -> (i32, i32, u32, u32) {
+    let fit = (window.0 as f64 / picture.0 as f64).min(window.1 as f64 / picture.1 as f64);
+    let scale = if integer && fit >= 1.0 { fit.floor() } else { fit };
+
+    let width = ((picture.0 as f64 * scale) as u32).max(1);
+    let height = ((picture.1 as f64 * scale) as u32).max(1);
+    let x = (window.0.saturating_sub(width) / 2) as i32;
+    let y = (window.1.saturating_sub(height) / 2) as i32;
+    (x, y, width, height)
+}
+
+impl Renderer for Graphics {
+    fn draw(&mut self, screen: &Screen) {
+        Graphics::draw(self, screen)
+    }
+
+    fn scale(&self) -> u32 {
+        SCALE
     }
-    
-}
\ No newline at end of file
+
+    fn palette(&self) -> Palette {
+        self.palette.clone()
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn fading(&self) -> bool {
+        // a resized window or the overlay has to be drawn again too
+        self.filter.fading()
+            || self.redraw
+            || self.osd.active()
+            || self.screen.output_size().is_ok_and(|size| size != self.window_size)
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        Graphics::toggle_fullscreen(self)
+    }
+
+    // shown in the window's overlay
+    fn message(&mut self, text: &str) {
+        self.osd.message(text);
+    }
+
+    fn set_status(&mut self, status: Status) {
+        self.osd.set_status(status);
+    }
+
+    fn toggle_osd(&mut self) {
+        self.osd.stats = !self.osd.stats;
+    }
+
+    fn set_keys(&mut self, keys: [bool; 16]) {
+        if self.keypad && keys != self.keys {
+            self.redraw = true;
+        }
+        self.keys = keys;
+    }
+
+    fn keypad_at(&self, x: i32, y: i32) -> Option<u8> {
+        Graphics::keypad_at(self, x, y)
+    }
+}
+
+// The screen blown up to about 1280 pixels across, see frontend::scale_for
+fn picture(colors: &[Rgb], width: usize, height: usize, scaling: &ScaleOptions) -> scaler::Image {
+    scaler::render(colors, width, height, frontend::scale_for(SCALE, width) as usize, scaling)
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::{picture, viewport};
+    use graphics::scaler::ScaleOptions;
+
+    #[test]
+    fn test_picture_follows_resolution() {
+        let size = |width: usize, height: usize| {
+            let image = picture(&vec![(0, 0, 0); width * height], width, height, &ScaleOptions::default());
+            (image.width, image.height)
+        };
+        assert_eq!(size(64, 32), (1280, 640));
+        // 00FF
+        assert_eq!(size(128, 64), (1280, 640));
+        // the ETI-660 and MegaChip's color screen need a new texture
+        assert_eq!(size(64, 48), (1280, 960));
+        assert_eq!(size(256, 192), (1280, 960));
+    }
+
+    #[test]
+    fn test_viewport_letterboxes() {
+        // a wide window gets bars left and right
+        assert_eq!(viewport((1600, 640), (1280, 640), false), (160, 0, 1280, 640));
+        // a tall one above and below
+        assert_eq!(viewport((640, 640), (1280, 640), false), (0, 160, 640, 320));
+    }
+
+    #[test]
+    fn test_viewport_integer() {
+        assert_eq!(viewport((1000, 1000), (128, 64), false), (0, 250, 1000, 500));
+        assert_eq!(viewport((1000, 1000), (128, 64), true), (52, 276, 896, 448));
+        // too small to fit once, fall back to shrinking
+        assert_eq!(viewport((64, 64), (128, 64), true), (0, 16, 64, 32));
+    }
+}
@@ -0,0 +1,84 @@
+// A part of the window as (x, y, width, height), in output pixels.
+pub type Area = (i32, i32, u32, u32);
+
+// The COSMAC VIP's keypad, as the default bindings lay it over 1234/QWER/ASDF/ZXCV
+pub const LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Splits the window into the game on the left and a square keypad panel on
+// the right, which never takes more than a third of the width.
+pub fn split(window: (u32, u32)) -> (Area, Area) {
+    let side = window.1.min(window.0 / 3);
+    let game = (0, 0, window.0 - side, window.1);
+    let panel = ((window.0 - side) as i32, ((window.1 - side) / 2) as i32, side, side);
+    (game, panel)
+}
+
+// Each key and where its button is drawn, leaving a gap between buttons
+pub fn keys(panel: Area) -> Vec<(u8, Area)> {
+    let cell = panel.2 / 4;
+    let gap = (cell / 10).max(1).min(cell / 2);
+    let mut keys = Vec::with_capacity(16);
+    for (row, line) in LAYOUT.iter().enumerate() {
+        for (column, &key) in line.iter().enumerate() {
+            let x = panel.0 + (column as u32 * cell + gap) as i32;
+            let y = panel.1 + (row as u32 * cell + gap) as i32;
+            keys.push((key, (x, y, cell - gap * 2, cell - gap * 2)));
+        }
+    }
+    keys
+}
+
+// The key under a point, counting the gaps around a button as part of it
+pub fn key_at(panel: Area, x: i32, y: i32) -> Option<u8> {
+    let cell = (panel.2 / 4) as i32;
+    if cell == 0 {
+        return None;
+    }
+    let (column, row) = ((x - panel.0).div_euclid(cell), (y - panel.1).div_euclid(cell));
+    if (0..4).contains(&column) && (0..4).contains(&row) {
+        Some(LAYOUT[row as usize][column as usize])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::keypad::{key_at, keys, split};
+
+    #[test]
+    fn test_split() {
+        let (game, panel) = split((1920, 640));
+        assert_eq!(game, (0, 0, 1280, 640));
+        assert_eq!(panel, (1280, 0, 640, 640));
+        // a tall window centres the panel
+        let (_, panel) = split((900, 900));
+        assert_eq!(panel, (600, 300, 300, 300));
+    }
+
+    #[test]
+    fn test_key_at() {
+        let panel = (1280, 0, 640, 640);
+        assert_eq!(key_at(panel, 1281, 1), Some(0x1));
+        assert_eq!(key_at(panel, 1919, 639), Some(0xF));
+        assert_eq!(key_at(panel, 1280 + 160 * 3 + 5, 5), Some(0xC));
+        assert_eq!(key_at(panel, 1280 + 170, 490), Some(0x0));
+        // the game, not the panel
+        assert_eq!(key_at(panel, 1279, 10), None);
+        assert_eq!(key_at(panel, 1300, 640), None);
+    }
+
+    #[test]
+    fn test_keys_inside_their_cells() {
+        let panel = (1280, 0, 640, 640);
+        for (key, (x, y, width, height)) in keys(panel) {
+            assert_eq!(key_at(panel, x, y), Some(key));
+            assert_eq!(key_at(panel, x + width as i32 - 1, y + height as i32 - 1), Some(key));
+        }
+    }
+}
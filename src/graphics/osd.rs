@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use frontend::Status;
+use graphics::scaler::Image;
+
+// How long each message stays up
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// Text drawn over the game: the stats line, a pause marker and the messages
+// the emulator has for the player, one after another.
+pub struct Osd {
+    // whether the FPS line is shown, messages and the pause marker always are
+    pub stats: bool,
+    status: Status,
+    // waiting their turn, the first has been up since `shown`
+    messages: VecDeque<String>,
+    shown: Option<Instant>,
+}
+
+impl Default for Osd {
+    fn default() -> Osd {
+        Osd::new()
+    }
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd { stats: false, status: Status::default(), messages: VecDeque::new(), shown: None }
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        self.status = status;
+    }
+
+    pub fn message(&mut self, text: &str) {
+        self.messages.push_back(String::from(text));
+    }
+
+    // True while there's anything to show, so the picture keeps being redrawn
+    pub fn active(&self) -> bool {
+        self.stats || self.status.paused || !self.messages.is_empty()
+    }
+
+    pub fn draw(&mut self, image: &mut Image) {
+        if self.shown.is_some_and(|shown| shown.elapsed() > MESSAGE_TIME) {
+            self.messages.pop_front();
+            self.shown = None;
+        }
+
+        // about 1/100th of the picture per font pixel, so text keeps its size
+        // relative to the game whatever the scaler does
+        let dot = (image.height / 100).max(1);
+        let margin = dot * 2;
+
+        if self.stats {
+            let line = format!("{:.0} FPS {:.0} IPS", self.status.fps, self.status.ips);
+            write(image, margin, margin, dot, &line);
+        }
+        if self.status.paused {
+            let x = image.width.saturating_sub(text_width("PAUSED", dot) + margin);
+            write(image, x, margin, dot, "PAUSED");
+        }
+        if let Some(text) = self.messages.front() {
+            self.shown.get_or_insert_with(Instant::now);
+            let y = image.height.saturating_sub(GLYPH_HEIGHT * dot + margin * 2);
+            write(image, margin, y, dot, text);
+        }
+    }
+}
+
+fn text_width(text: &str, dot: usize) -> usize {
+    (text.chars().count() * (GLYPH_WIDTH + 1) + 1) * dot
+}
+
+// White text on a darkened box, so it reads on any palette.
+fn write(image: &mut Image, x: usize, y: usize, dot: usize, text: &str) {
+    let (width, height) = (text_width(text, dot), (GLYPH_HEIGHT + 2) * dot);
+    for py in y..(y + height).min(image.height) {
+        for px in x..(x + width).min(image.width) {
+            let i = (py * image.width + px) * 3;
+            for channel in &mut image.pixels[i..i + 3] {
+                *channel /= 3;
+            }
+        }
+    }
+
+    for (n, c) in text.chars().enumerate() {
+        let left = x + (n * (GLYPH_WIDTH + 1) + 1) * dot;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) != 0 {
+                    fill(image, left + column * dot, y + (row + 1) * dot, dot);
+                }
+            }
+        }
+    }
+}
+
+fn fill(image: &mut Image, x: usize, y: usize, dot: usize) {
+    for py in y..(y + dot).min(image.height) {
+        for px in x..(x + dot).min(image.width) {
+            let i = (py * image.width + px) * 3;
+            image.pixels[i..i + 3].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        }
+    }
+}
+
+// A 3x5 font, one byte per row with the leftmost pixel in bit 2. Lower case
+// is drawn as upper case, anything else missing as a question mark.
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::Status;
+    use graphics::osd::{glyph, Osd, MESSAGE_TIME};
+    use graphics::scaler::Image;
+
+    fn blank(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![0x60; width * height * 3] }
+    }
+
+    #[test]
+    fn test_lower_case_and_unknown() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+
+    #[test]
+    fn test_nothing_drawn_when_idle() {
+        let mut osd = Osd::new();
+        let mut image = blank(200, 100);
+        assert!(!osd.active());
+        osd.draw(&mut image);
+        assert!(image.pixels.iter().all(|&channel| channel == 0x60));
+    }
+
+    #[test]
+    fn test_messages_take_turns() {
+        let mut osd = Osd::new();
+        osd.message("first");
+        osd.message("second");
+        osd.draw(&mut blank(200, 100));
+        assert_eq!(osd.messages.front().map(String::as_str), Some("first"));
+
+        // once the first has had its time the second shows
+        osd.shown = osd.shown.map(|shown| shown - MESSAGE_TIME * 2);
+        osd.draw(&mut blank(200, 100));
+        assert_eq!(osd.messages.front().map(String::as_str), Some("second"));
+        assert!(osd.shown.is_some_and(|shown| shown.elapsed() < MESSAGE_TIME));
+    }
+
+    #[test]
+    fn test_pause_marker_top_right() {
+        let mut osd = Osd::new();
+        osd.set_status(Status { fps: 0.0, ips: 0.0, paused: true });
+        assert!(osd.active());
+
+        let mut image = blank(200, 100);
+        osd.draw(&mut image);
+        let lit = |x: usize, y: usize| image.pixels[(y * 200 + x) * 3] == 0xFF;
+        // "PAUSED" is 25 dots wide ending 2 dots from the edge, its P starts
+        // one dot in and one dot down
+        assert!(lit(174, 3));
+        assert!(!(0..100).any(|x| (0..100).any(|y| lit(x, y))));
+    }
+}
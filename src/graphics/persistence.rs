@@ -0,0 +1,173 @@
+use framebuffer::Screen;
+use palette::{Palette, Rgb};
+
+// Below this a fading pixel is just background
+const VISIBLE: f32 = 1.0 / 64.0;
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+// Sprites are erased and redrawn with XOR every frame, so moving objects
+// flicker. These fake the slow phosphor of the CRTs the games were made for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Persistence {
+    Off,
+    // a pixel that goes dark keeps this much of its brightness each frame
+    Phosphor(f32),
+    // a pixel is lit if it was lit in this frame or the one before
+    Blend,
+}
+
+impl Persistence {
+    // "off", "blend", "phosphor" or "phosphor:0.7"
+    pub fn parse(spec: &str) -> Result<Persistence, String> {
+        let mut parts = spec.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("off"), None) => Ok(Persistence::Off),
+            (Some("blend"), None) => Ok(Persistence::Blend),
+            (Some("phosphor"), None) => Ok(Persistence::Phosphor(DEFAULT_DECAY)),
+            (Some("phosphor"), Some(decay)) => match decay.parse::<f32>() {
+                Ok(decay) if (0.0..1.0).contains(&decay) => Ok(Persistence::Phosphor(decay)),
+                _ => Err(format!("Phosphor decay {} must be at least 0 and below 1", decay))
+            },
+            _ => Err(format!("Unknown display filter {}", spec))
+        }
+    }
+}
+
+// Turns each emulated frame into the colors actually shown.
+pub struct Filter {
+    mode: Persistence,
+    intensity: Vec<f32>,
+    // palette index of each pixel when it was last lit, so it fades in its own color
+    lit: Vec<u8>,
+    previous: Vec<u8>,
+    // whether the last frame differed from the one before it
+    changed: bool,
+}
+
+impl Filter {
+    pub fn new(mode: Persistence) -> Filter {
+        Filter {
+            mode,
+            intensity: Vec::new(),
+            lit: Vec::new(),
+            previous: Vec::new(),
+            changed: false,
+        }
+    }
+
+    pub fn mode(&self) -> Persistence {
+        self.mode
+    }
+
+    // Call once per 60 Hz frame; the result is one color per pixel, row by row.
+    pub fn apply(&mut self, screen: &Screen, palette: &Palette) -> Vec<Rgb> {
+        // a different screen size starts over from a blank one
+        let size = screen.pixels().len();
+        if self.previous.len() != size {
+            self.intensity = vec![0.0; size];
+            self.lit = vec![0; size];
+            self.previous = vec![0; size];
+        }
+
+        let pixels = screen.pixels().iter().cloned();
+        let colors = match self.mode {
+            Persistence::Off => pixels.map(|pixel| palette.color(pixel)).collect(),
+            Persistence::Blend => pixels.zip(self.previous.iter())
+                .map(|(pixel, &previous)| palette.color(if pixel != 0 { pixel } else { previous }))
+                .collect(),
+            Persistence::Phosphor(decay) => {
+                let background = palette.color(0);
+                pixels.enumerate().map(|(i, pixel)| {
+                    if pixel != 0 {
+                        self.intensity[i] = 1.0;
+                        self.lit[i] = pixel;
+                    } else {
+                        self.intensity[i] *= decay;
+                        if self.intensity[i] < VISIBLE {
+                            self.intensity[i] = 0.0;
+                        }
+                    }
+                    mix(background, palette.color(self.lit[i]), self.intensity[i])
+                }).collect()
+            }
+        };
+
+        self.changed = screen.pixels() != &self.previous[..];
+        self.previous = screen.pixels().to_vec();
+        colors
+    }
+
+    // True while the picture would keep changing with no new frame drawn.
+    pub fn fading(&self) -> bool {
+        match self.mode {
+            Persistence::Off => false,
+            // the frame before still has to drop out
+            Persistence::Blend => self.changed,
+            Persistence::Phosphor(_) => self.intensity.iter()
+                .zip(self.previous.iter())
+                .any(|(&intensity, &pixel)| pixel == 0 && intensity > 0.0),
+        }
+    }
+}
+
+fn mix(from: Rgb, to: Rgb, amount: f32) -> Rgb {
+    let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+    (channel(from.0, to.0), channel(from.1, to.1), channel(from.2, to.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use framebuffer::Screen;
+    use graphics::persistence::{Filter, Persistence};
+    use palette::Palette;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Persistence::parse("phosphor:0.5"), Ok(Persistence::Phosphor(0.5)));
+        assert_eq!(Persistence::parse("blend"), Ok(Persistence::Blend));
+        assert!(Persistence::parse("phosphor:1.5").is_err());
+        assert!(Persistence::parse("crt").is_err());
+    }
+
+    #[test]
+    fn test_phosphor_decays() {
+        let palette = Palette::parse("#000000,#c80000").unwrap();
+        let mut filter = Filter::new(Persistence::Phosphor(0.5));
+        let mut screen = Screen::default();
+        screen[0][1] = 1;
+        assert_eq!(filter.apply(&screen, &palette)[1], (200, 0, 0));
+        assert!(!filter.fading());
+
+        screen[0][1] = 0;
+        assert_eq!(filter.apply(&screen, &palette)[1], (100, 0, 0));
+        assert!(filter.fading());
+        assert_eq!(filter.apply(&screen, &palette)[1], (50, 0, 0));
+
+        for _ in 0..10 {
+            filter.apply(&screen, &palette);
+        }
+        assert_eq!(filter.apply(&screen, &palette)[1], (0, 0, 0));
+        assert!(!filter.fading());
+    }
+
+    #[test]
+    fn test_blend_ors_two_frames() {
+        let palette = Palette::default();
+        let mut filter = Filter::new(Persistence::Blend);
+        let mut screen = Screen::default();
+        screen[0][0] = 1;
+        filter.apply(&screen, &palette);
+
+        screen[0][0] = 0;
+        screen[0][1] = 1;
+        let colors = filter.apply(&screen, &palette);
+        assert_eq!(colors[0], palette.color(1));
+        assert_eq!(colors[1], palette.color(1));
+        assert!(filter.fading());
+
+        let colors = filter.apply(&screen, &palette);
+        assert_eq!(colors[0], palette.color(0));
+        assert!(!filter.fading());
+    }
+}
@@ -0,0 +1,237 @@
+use palette::Rgb;
+
+// How the emulated pixels are blown up before the window stretches them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Upscale {
+    Nearest,
+    // EPX, rounds off diagonal steps
+    Scale2x,
+    Scale3x,
+}
+
+impl Upscale {
+    pub fn parse(name: &str) -> Result<Upscale, String> {
+        match name {
+            "nearest" => Ok(Upscale::Nearest),
+            "scale2x" | "epx" => Ok(Upscale::Scale2x),
+            "scale3x" => Ok(Upscale::Scale3x),
+            _ => Err(format!("Unknown scaler {}, try nearest, scale2x or scale3x", name))
+        }
+    }
+
+    fn factor(self) -> usize {
+        match self {
+            Upscale::Nearest => 1,
+            Upscale::Scale2x => 2,
+            Upscale::Scale3x => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScaleOptions {
+    pub upscale: Upscale,
+    // darken the bottom of every row like the gaps between CRT lines
+    pub scanlines: bool,
+    // outline every emulated pixel
+    pub grid: bool,
+    // only stretch to the window by whole multiples
+    pub integer: bool,
+}
+
+impl Default for ScaleOptions {
+    fn default() -> ScaleOptions {
+        ScaleOptions { upscale: Upscale::Nearest, scanlines: false, grid: false, integer: false }
+    }
+}
+
+// An RGB24 image ready to go into a texture.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+// Scales a width x height picture up to about `scale` host pixels per
+// emulated pixel. The result is a whole multiple of the filter's factor, so
+// scale3x at 20 gives 18, and the window makes up the difference.
+pub fn render(colors: &[Rgb], width: usize, height: usize, scale: usize, options: &ScaleOptions) -> Image {
+    let factor = options.upscale.factor();
+    let (filtered, fw, fh) = match options.upscale {
+        Upscale::Nearest => (colors.to_vec(), width, height),
+        Upscale::Scale2x => (scale2x(colors, width, height), width * 2, height * 2),
+        Upscale::Scale3x => (scale3x(colors, width, height), width * 3, height * 3),
+    };
+
+    let repeat = (scale / factor).max(1);
+    let cell = factor * repeat;
+    let (out_width, out_height) = (fw * repeat, fh * repeat);
+    let scanline = (repeat / 3).max(1);
+
+    let mut pixels = Vec::with_capacity(out_width * out_height * 3);
+    for y in 0..out_height {
+        let dark_line = options.scanlines && repeat > 1 && y % repeat >= repeat - scanline;
+        let grid_line = options.grid && cell > 2 && y % cell == cell - 1;
+        let row = &filtered[(y / repeat) * fw..(y / repeat + 1) * fw];
+        for x in 0..out_width {
+            let mut color = row[x / repeat];
+            if dark_line {
+                color = dim(color, 2);
+            }
+            if grid_line || (options.grid && cell > 2 && x % cell == cell - 1) {
+                color = dim(color, 4);
+            }
+            pixels.extend_from_slice(&[color.0, color.1, color.2]);
+        }
+    }
+
+    Image { width: out_width, height: out_height, pixels }
+}
+
+// takes 1/amount off each channel
+fn dim(color: Rgb, amount: u8) -> Rgb {
+    (color.0 - color.0 / amount, color.1 - color.1 / amount, color.2 - color.2 / amount)
+}
+
+// Neighbours past the edge repeat the edge pixel.
+fn at(colors: &[Rgb], width: usize, height: usize, x: isize, y: isize) -> Rgb {
+    let x = x.max(0).min(width as isize - 1) as usize;
+    let y = y.max(0).min(height as isize - 1) as usize;
+    colors[y * width + x]
+}
+
+// Scale2x/EPX: each pixel E becomes a 2x2 block, corners taking the color
+// of the two neighbours they touch when those agree.
+//
+//     B        E0 E1
+//   D E F  ->  E2 E3
+//     H
+pub fn scale2x(colors: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+    let mut out = vec![(0, 0, 0); width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            let e = at(colors, width, height, xi, yi);
+            let b = at(colors, width, height, xi, yi - 1);
+            let d = at(colors, width, height, xi - 1, yi);
+            let f = at(colors, width, height, xi + 1, yi);
+            let h = at(colors, width, height, xi, yi + 1);
+
+            let mut block = [e; 4];
+            if b != h && d != f {
+                if d == b { block[0] = d; }
+                if b == f { block[1] = f; }
+                if d == h { block[2] = d; }
+                if h == f { block[3] = f; }
+            }
+
+            let (ox, oy) = (x * 2, y * 2);
+            out[oy * width * 2 + ox] = block[0];
+            out[oy * width * 2 + ox + 1] = block[1];
+            out[(oy + 1) * width * 2 + ox] = block[2];
+            out[(oy + 1) * width * 2 + ox + 1] = block[3];
+        }
+    }
+    out
+}
+
+// Scale3x, the same idea as scale2x over a 3x3 block.
+//
+//   A B C      E0 E1 E2
+//   D E F  ->  E3 E4 E5
+//   G H I      E6 E7 E8
+pub fn scale3x(colors: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+    let mut out = vec![(0, 0, 0); width * height * 9];
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            let pick = |dx: isize, dy: isize| at(colors, width, height, xi + dx, yi + dy);
+            let (a, b, c) = (pick(-1, -1), pick(0, -1), pick(1, -1));
+            let (d, e, f) = (pick(-1, 0), pick(0, 0), pick(1, 0));
+            let (g, h, i) = (pick(-1, 1), pick(0, 1), pick(1, 1));
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                if d == b { block[0] = d; }
+                if (d == b && e != c) || (b == f && e != a) { block[1] = b; }
+                if b == f { block[2] = f; }
+                if (d == b && e != g) || (d == h && e != a) { block[3] = d; }
+                if (b == f && e != i) || (h == f && e != c) { block[5] = f; }
+                if d == h { block[6] = d; }
+                if (d == h && e != i) || (h == f && e != g) { block[7] = h; }
+                if h == f { block[8] = f; }
+            }
+
+            for (n, &color) in block.iter().enumerate() {
+                let (ox, oy) = (x * 3 + n % 3, y * 3 + n / 3);
+                out[oy * width * 3 + ox] = color;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::scaler::{render, scale2x, scale3x, ScaleOptions, Upscale};
+
+    const K: (u8, u8, u8) = (0, 0, 0);
+    const W: (u8, u8, u8) = (200, 200, 200);
+
+    #[test]
+    fn test_scale2x_rounds_diagonal() {
+        // a diagonal step
+        let colors = [
+            W, K,
+            K, K,
+        ];
+        let out = scale2x(&colors, 2, 2);
+        // the lit pixel's inner corner is cut off
+        assert_eq!(&out[0..4], &[W, W, K, K]);
+        assert_eq!(out[4], W);
+        assert_eq!(out[5], K);
+    }
+
+    #[test]
+    fn test_scale2x_keeps_flat_areas() {
+        let colors = [W; 9];
+        assert_eq!(scale2x(&colors, 3, 3), vec![W; 36]);
+        assert_eq!(scale3x(&colors, 3, 3), vec![W; 81]);
+    }
+
+    #[test]
+    fn test_scale3x_line() {
+        // a diagonal line of two pixels
+        let colors = [
+            W, K,
+            K, W,
+        ];
+        let out = scale3x(&colors, 2, 2);
+        // the gap between the two pixels is filled in from the dark pixel's side
+        assert_eq!(out[2 * 6 + 3], W);
+        assert_eq!(out[6 + 3], W);
+        assert_eq!(out[3], K);
+    }
+
+    #[test]
+    fn test_render_sizes() {
+        let colors = [W; 4];
+        let options = ScaleOptions { upscale: Upscale::Scale3x, ..Default::default() };
+        let image = render(&colors, 2, 2, 20, &options);
+        assert_eq!((image.width, image.height), (36, 36));
+        assert_eq!(image.pixels.len(), 36 * 36 * 3);
+    }
+
+    #[test]
+    fn test_scanlines_and_grid() {
+        let colors = [W];
+        let options = ScaleOptions { upscale: Upscale::Nearest, scanlines: true, grid: true, integer: false };
+        let image = render(&colors, 1, 1, 6, &options);
+        let pixel = |x: usize, y: usize| image.pixels[(y * 6 + x) * 3];
+        assert_eq!(pixel(0, 0), 200);
+        // bottom two rows are scanline, the last one is also grid
+        assert_eq!(pixel(0, 4), 100);
+        assert_eq!(pixel(0, 5), 75);
+        assert_eq!(pixel(5, 0), 150);
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Eq, PartialEq, Debug)]
+use platform::Platform;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum Instruction {
     // PC = location
     Jmp {location: u16},
@@ -32,14 +34,14 @@ pub enum Instruction {
     // V[x] -= V[y]; VF = !borrow
     Sub {regx: u8, regy: u8},
 
-    // V[x] >>= 1; VF = lsb(V[x])
-    Shr {reg: u8},
+    // V[x] >>= 1; VF = lsb(V[x]), shifts V[y] into V[x] with the shift quirk
+    Shr {regx: u8, regy: u8},
 
     // V[x] = V[y] - V[x]; VF = !borrow
     Subn {regx: u8, regy: u8},
 
-    // V[x] <<= 1; VF = msb(V[x])
-    Shl {reg: u8},
+    // V[x] <<= 1; VF = msb(V[x]), shifts V[y] into V[x] with the shift quirk
+    Shl {regx: u8, regy: u8},
 
     // If V[x] == byte -> pc += 2
     ConstantSe {reg: u8, byte: u8},
@@ -86,6 +88,9 @@ pub enum Instruction {
     // skip if key not pressed
     Sknp {key: u8},
 
+    // wait for a key press, V[x] = key
+    WaitKey {reg: u8},
+
     // V[x] = rand & byte
     Random {reg: u8, byte: u8},
 
@@ -93,9 +98,88 @@ pub enum Instruction {
     Draw{x: u8, y: u8, n: u16},
     Clear,
 
+    // Run machine code at nnn (COSMAC VIP)
+    Sys {location: u16},
+
+    // SUPER-CHIP: scroll down n lines, right or left 4 pixels
+    ScrollDown {n: u8},
+    ScrollRight,
+    ScrollLeft,
+    // SUPER-CHIP: quit the interpreter
+    Exit,
+    // SUPER-CHIP: switch to 64x32 or 128x64
+    LowRes,
+    HighRes,
+    // SUPER-CHIP: I = big sprite of V[x]
+    BigFont {reg: u8},
+    // SUPER-CHIP: flags[0..x] = V[0..x]
+    SaveFlags {reg: u8},
+    // SUPER-CHIP: V[0..x] = flags[0..x]
+    LoadFlags {reg: u8},
+
+    // XO-CHIP: scroll up n lines
+    ScrollUp {n: u8},
+    // XO-CHIP: mem[I..] = V[x..=y]
+    SaveRange {regx: u8, regy: u8},
+    // XO-CHIP: V[x..=y] = mem[I..]
+    LoadRange {regx: u8, regy: u8},
+    // XO-CHIP: I = the next 16 bit word
+    LdILong,
+    // XO-CHIP: draw on bit planes n
+    Plane {n: u8},
+    // XO-CHIP: load the audio pattern at I
+    Audio,
+    // XO-CHIP: pitch = V[x]
+    Pitch {reg: u8},
+
+    // CHIP-8X: V[x] += V[y] a nibble at a time, each mod 8
+    AddNibbles {regx: u8, regy: u8},
+    // CHIP-8X: color the zones V[x] and V[x + 1] pick with V[y]
+    ColorZones {x: u8, y: u8},
+    // CHIP-8X: color n rows of the 8 pixels at V[x], V[x + 1] with V[y]
+    ColorRows {x: u8, y: u8, n: u8},
+    // CHIP-8X: step the background color
+    NextBackground,
+    // CHIP-8X: skip if key V[x] is or isn't pressed on the second keypad
+    Skp2 {key: u8},
+    Sknp2 {key: u8},
+    // CHIP-8X: out 3 = V[x], the VP-595's tone
+    Output {reg: u8},
+    // CHIP-8X: V[x] = in 3
+    Input {reg: u8},
+
+    // MegaChip: back to the CHIP-8 screen, or on to the 256x192 color one
+    MegaOff,
+    MegaOn,
+    // MegaChip: I = high << 16 | the next 16 bit word
+    LdIHuge {high: u8},
+    // MegaChip: load n colors from I into the palette
+    LoadPalette {n: u8},
+    // MegaChip: sprites are n pixels wide or high, 0 is 256
+    SpriteWidth {n: u8},
+    SpriteHeight {n: u8},
+    // MegaChip: how much of the screen shows
+    ScreenAlpha {alpha: u8},
+    // MegaChip: play the sample at I, looping if n is 0
+    PlaySample {n: u8},
+    StopSample,
+    // MegaChip: how sprites mix with what's under them
+    BlendMode {n: u8},
+
     Unknown
 }
 
+impl Instruction {
+    // Bytes the instruction takes up: XO-CHIP's F000 nnnn and MegaChip's
+    // 01nn nnnn carry an address in the word after them
+    pub fn length(&self) -> usize {
+        match *self {
+            Instruction::LdILong | Instruction::LdIHuge {..} => 4,
+            _ => 2
+        }
+    }
+}
+
 // 0xF123 -> 0xF
 fn high(op: u16) -> u16 {
     (op & 0xF000) >> 12
@@ -130,14 +214,20 @@ pub fn convert_op(op: u16) -> Instruction {
 
         //0x00
         0x0     => {
-            match low(op) {
-                0x0 => {
-                    Instruction::Clear
-                },
-                0xE => {
-                    Instruction::Ret
-                },
-                _ => {println!("{:x}", op); Instruction::Unknown}
+            match op {
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                // 0x00Cn
+                _ if op & 0xFFF0 == 0x00C0 => Instruction::ScrollDown { n: low(op) as u8 },
+                // 0x00Dn
+                _ if op & 0xFFF0 == 0x00D0 => Instruction::ScrollUp { n: low(op) as u8 },
+                // 0x0nnn
+                _ => Instruction::Sys { location: nnn(op) }
             }
         }
 
@@ -153,8 +243,17 @@ pub fn convert_op(op: u16) -> Instruction {
         // 0x4xkk
         0x4     => Instruction::ConstantSne { reg: x(op), byte: kk(op) },
 
-        // 0x5xy0
-        0x5     => Instruction::Se {x: x(op), y: y(op) },
+        0x5     => {
+            match low(op) {
+                // 0x5xy0
+                0x0 => Instruction::Se {x: x(op), y: y(op) },
+                // 0x5xy2
+                0x2 => Instruction::SaveRange {regx: x(op), regy: y(op) },
+                // 0x5xy3
+                0x3 => Instruction::LoadRange {regx: x(op), regy: y(op) },
+                _ => Instruction::Unknown
+            }
+        },
 
         // 0x6xkk  
         0x6     => Instruction::Set { reg: x(op), byte: kk(op) },
@@ -179,14 +278,14 @@ pub fn convert_op(op: u16) -> Instruction {
                 0x4     => Instruction::Add {regx: x, regy: y},
                 // 0x8xy5
                 0x5     => Instruction::Sub {regx: x, regy: y},
-                // 0x8x_6
-                0x6     => Instruction::Shr {reg: x},
+                // 0x8xy6
+                0x6     => Instruction::Shr {regx: x, regy: y},
                 // 0x8xy7
                 0x7     => Instruction::Subn {regx: x, regy: y},
-                // 0x8x_E
-                0xE     => Instruction::Shl {reg: x},
+                // 0x8xyE
+                0xE     => Instruction::Shl {regx: x, regy: y},
 
-                _       => {println!("{:x}", op); Instruction::Unknown}
+                _       => Instruction::Unknown
             }
         }
         // 0x9xy0
@@ -236,9 +335,21 @@ pub fn convert_op(op: u16) -> Instruction {
         0xF     => {
             let x = x(op);
             match kk(op) {
+                0x00 if x == 0 => {
+                    Instruction::LdILong
+                },
+                0x01        => {
+                    Instruction::Plane {n: x}
+                },
+                0x02 if x == 0 => {
+                    Instruction::Audio
+                },
                 0x07        => {
                     Instruction::LdDelay {reg: x}
                 },
+                0x0A        => {
+                    Instruction::WaitKey {reg: x}
+                },
                 0x15        => {
                     Instruction::SetDelay {reg: x}
                 },
@@ -248,27 +359,97 @@ pub fn convert_op(op: u16) -> Instruction {
                 0x29        => {
                     Instruction::LdFont {reg: x}
                 }
+                0x30        => {
+                    Instruction::BigFont {reg: x}
+                }
                 0x33        => {
                     Instruction::LdB {reg: x}
                 },
+                0x3A        => {
+                    Instruction::Pitch {reg: x}
+                },
                 0x55        => {
                     Instruction::SetLong {reg: x}
                 }
                 0x65        => {
                     Instruction::LdLong {reg: x}
                 }
-                _       => {println!("{:x}", op); Instruction::Unknown}
+                0x75        => {
+                    Instruction::SaveFlags {reg: x}
+                }
+                0x85        => {
+                    Instruction::LoadFlags {reg: x}
+                }
+                _       => Instruction::Unknown
             }
         }
 
-        _       => {println!("{:x}", op); return Instruction::Unknown}
+        _       => Instruction::Unknown
+    }
+}
+
+// CHIP-8X took over Bnnn for color and 02A0, 5xy1 and a few Ex and Fx
+// opcodes for its I/O; everything else decodes as it would for the VIP.
+pub fn convert_chip8x_op(op: u16) -> Instruction {
+    match (high(op), low(op), kk(op)) {
+        _ if op == 0x02A0 => Instruction::NextBackground,
+        // 0x5xy1
+        (0x5, 0x1, _) => Instruction::AddNibbles { regx: x(op), regy: y(op) },
+        // 0xBxy0
+        (0xB, 0x0, _) => Instruction::ColorZones { x: x(op), y: y(op) },
+        // 0xBxyn
+        (0xB, n, _) => Instruction::ColorRows { x: x(op), y: y(op), n: n as u8 },
+        // 0xExF2
+        (0xE, _, 0xF2) => Instruction::Skp2 { key: x(op) },
+        // 0xExF5
+        (0xE, _, 0xF5) => Instruction::Sknp2 { key: x(op) },
+        // 0xFxF8
+        (0xF, _, 0xF8) => Instruction::Output { reg: x(op) },
+        // 0xFxFB
+        (0xF, _, 0xFB) => Instruction::Input { reg: x(op) },
+        _ => convert_op(op)
+    }
+}
+
+// MegaChip's instructions are all 00nn to 08nn, where the VIP only had its
+// machine code calls.
+pub fn convert_megachip_op(op: u16) -> Instruction {
+    match (op >> 8, kk(op)) {
+        (0x00, 0x10) => Instruction::MegaOff,
+        (0x00, 0x11) => Instruction::MegaOn,
+        // 0x01nn nnnn
+        (0x01, high) => Instruction::LdIHuge { high },
+        // 0x02nn
+        (0x02, n) => Instruction::LoadPalette { n },
+        // 0x03nn
+        (0x03, n) => Instruction::SpriteWidth { n },
+        // 0x04nn
+        (0x04, n) => Instruction::SpriteHeight { n },
+        // 0x05nn
+        (0x05, alpha) => Instruction::ScreenAlpha { alpha },
+        // 0x060n
+        (0x06, n) if n < 0x10 => Instruction::PlaySample { n },
+        (0x07, 0x00) => Instruction::StopSample,
+        // 0x080n
+        (0x08, n) if n < 0x10 => Instruction::BlendMode { n },
+        _ => convert_op(op)
+    }
+}
+
+// An opcode as the platform reads it, plain CHIP-8 if it isn't known
+pub fn convert_platform_op(op: u16, platform: Option<Platform>) -> Instruction {
+    match platform {
+        Some(Platform::Chip8X) => convert_chip8x_op(op),
+        Some(Platform::MegaChip) => convert_megachip_op(op),
+        _ => convert_op(op)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use instruction::Instruction::*;
-    use instruction::convert_op;
+    use instruction::{convert_chip8x_op, convert_megachip_op, convert_op, convert_platform_op};
+    use platform::Platform;
 
     #[test]
     fn test_conv_addo() {
@@ -312,4 +493,56 @@ mod tests {
         let instr = convert_op(0xF31E);
         assert_eq!(instr, AddI {reg: 3})
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_conv_variants() {
+        assert_eq!(convert_op(0x00E0), Clear);
+        assert_eq!(convert_op(0x00FF), HighRes);
+        assert_eq!(convert_op(0x00C4), ScrollDown {n: 4});
+        assert_eq!(convert_op(0x0123), Sys {location: 0x123});
+        assert_eq!(convert_op(0xF000), LdILong);
+        assert_eq!(convert_op(0xF201), Plane {n: 2});
+        assert_eq!(convert_op(0x5123), LoadRange {regx: 1, regy: 2});
+        assert_eq!(convert_op(0x5121), Unknown);
+        assert_eq!(convert_op(0xF375), SaveFlags {reg: 3});
+    }
+
+    #[test]
+    fn test_conv_wait_key() {
+        let instr = convert_op(0xF20A);
+        assert_eq!(instr, WaitKey {reg: 2})
+    }
+
+    #[test]
+    fn test_conv_chip8x() {
+        assert_eq!(convert_chip8x_op(0x02A0), NextBackground);
+        assert_eq!(convert_chip8x_op(0x5121), AddNibbles {regx: 1, regy: 2});
+        assert_eq!(convert_chip8x_op(0xB120), ColorZones {x: 1, y: 2});
+        assert_eq!(convert_chip8x_op(0xB124), ColorRows {x: 1, y: 2, n: 4});
+        assert_eq!(convert_chip8x_op(0xE3F2), Skp2 {key: 3});
+        assert_eq!(convert_chip8x_op(0xF3FB), Input {reg: 3});
+        assert_eq!(convert_chip8x_op(0x00E0), Clear);
+        // the VIP's meanings are untouched
+        assert_eq!(convert_op(0xB120), JmpA {loc: 0x120});
+        assert_eq!(convert_op(0x02A0), Sys {location: 0x2A0});
+    }
+
+    #[test]
+    fn test_conv_megachip() {
+        assert_eq!(convert_megachip_op(0x0011), MegaOn);
+        assert_eq!(convert_megachip_op(0x0112), LdIHuge {high: 0x12});
+        assert_eq!(convert_megachip_op(0x0300), SpriteWidth {n: 0});
+        assert_eq!(convert_megachip_op(0x0601), PlaySample {n: 1});
+        assert_eq!(convert_megachip_op(0x0805), BlendMode {n: 5});
+        assert_eq!(convert_megachip_op(0x0820), Sys {location: 0x820});
+        assert_eq!(convert_megachip_op(0x00E0), Clear);
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(convert_platform_op(0x0112, Some(Platform::MegaChip)).length(), 4);
+        assert_eq!(convert_platform_op(0x0112, None).length(), 2);
+        assert_eq!(convert_op(0xF000).length(), 4);
+        assert_eq!(convert_op(0x00E0).length(), 2);
+    }
+}
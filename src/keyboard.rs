@@ -1,63 +1,139 @@
 use sdl2;
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::GameControllerSubsystem;
+
+use frontend::{Hotkey, Input};
+use keymap::{HostKeys, KeyMap};
+
+const BUTTONS: [Button; 15] = [
+    Button::A, Button::B, Button::X, Button::Y,
+    Button::Back, Button::Guide, Button::Start,
+    Button::LeftStick, Button::RightStick,
+    Button::LeftShoulder, Button::RightShoulder,
+    Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+const AXES: [Axis; 6] = [
+    Axis::LeftX, Axis::LeftY, Axis::RightX, Axis::RightY,
+    Axis::TriggerLeft, Axis::TriggerRight,
+];
 
 pub struct Keyboard {
-    pump: sdl2::EventPump
+    pump: sdl2::EventPump,
+    keymap: KeyMap,
+    controller_sub: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    hotkeys: Vec<Hotkey>,
+    // where the left mouse button is held, for the on-screen keypad
+    pointer: Option<(i32, i32)>
 }
 
 impl Keyboard {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Keyboard {
-        Keyboard { pump: sdl_context.event_pump().unwrap() }
+    pub fn new(sdl_context: &sdl2::Sdl, keymap: KeyMap) -> Keyboard {
+        // controllers that are already plugged in show up as added events
+        // on the first poll, same as ones plugged in later
+        Keyboard {
+            pump: sdl_context.event_pump().unwrap(),
+            keymap,
+            controller_sub: sdl_context.game_controller().unwrap(),
+            controllers: Vec::new(),
+            hotkeys: Vec::new(),
+            pointer: None
+        }
     }
 
     pub fn get_keys(&mut self) -> Option<[bool; 16]> {
         // if user hits quit or esc, return an Err and exit in main
         // TODO: uncouple this from main?
-        for event in self.pump.poll_iter() {
-            if let Event::Quit { .. } = event {
-                return None
+        let mut reload = false;
+        let events: Vec<Event> = self.pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit { .. } => return None,
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => reload = true,
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(hotkey) = hotkey(keycode) {
+                        self.hotkeys.push(hotkey);
+                    }
+                },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => self.pointer = Some((x, y)),
+                Event::MouseMotion { mousestate, x, y, .. } if mousestate.left() => self.pointer = Some((x, y)),
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => self.pointer = None,
+                Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|pad| pad.instance_id() != which);
+                },
+                _ => ()
+            }
+        }
+
+        if reload {
+            // keep playing with the old bindings if the file is broken
+            match self.keymap.reload() {
+                Ok(keymap) => self.keymap = keymap,
+                Err(e) => println!("Could not reload key bindings: {}", e)
             }
         }
 
         // borrowed from a gba emulator
         // gets keyboard and converts to scancode
-        let keys: Vec<Keycode> = self.pump.keyboard_state()
+        let mut host = HostKeys::default();
+        host.scancodes = self.pump.keyboard_state()
             .pressed_scancodes()
+            .collect::<Vec<Scancode>>();
+        host.keycodes = host.scancodes.iter()
+            .cloned()
             .filter_map(Keycode::from_scancode)
             .collect();
 
-        let mut keyboard = [false; 0x10];
-        for key in keys {
-            let i = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-            
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-            
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-
-                Keycode::Z => Some(0xA),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xB),
-                // side keys
-                Keycode::Num4 => Some(0xC),
-                Keycode::R => Some(0xD),
-                Keycode::F => Some(0xE),
-                Keycode::V => Some(0xF),
-                _ => None
-            };
-
-            if let Some(key_spot) = i {
-                keyboard[key_spot] = true;
-            }
+        for pad in &self.controllers {
+            host.buttons.extend(BUTTONS.iter().filter(|&&button| pad.button(button)));
+            host.axes.extend(AXES.iter().map(|&axis| (axis, pad.axis(axis))));
+        }
+
+        Some(self.keymap.pressed(&host))
+    }
 
+    fn add_controller(&mut self, index: u32) {
+        match self.controller_sub.open(index) {
+            Ok(pad) => {
+                // SDL sends an added event for pads it already told us about
+                if self.controllers.iter().all(|open| open.instance_id() != pad.instance_id()) {
+                    println!("Controller connected: {}", pad.name());
+                    self.controllers.push(pad);
+                }
+            },
+            Err(e) => println!("Could not open controller {}: {}", index, e)
         }
-        Some(keyboard)
     }
-}
\ No newline at end of file
+}
+
+fn hotkey(keycode: Keycode) -> Option<Hotkey> {
+    match keycode {
+        Keycode::F3 => Some(Hotkey::ToggleOsd),
+        Keycode::F6 => Some(Hotkey::Pause),
+        Keycode::F7 => Some(Hotkey::NextPalette),
+        Keycode::F9 => Some(Hotkey::RecordGif),
+        Keycode::F10 => Some(Hotkey::RecordFrames),
+        Keycode::F11 => Some(Hotkey::Fullscreen),
+        Keycode::F12 => Some(Hotkey::Screenshot),
+        _ => None
+    }
+}
+
+impl Input for Keyboard {
+    fn get_keys(&mut self) -> Option<[bool; 16]> {
+        Keyboard::get_keys(self)
+    }
+
+    fn hotkeys(&mut self) -> Vec<Hotkey> {
+        ::std::mem::take(&mut self.hotkeys)
+    }
+
+    fn pointer(&self) -> Option<(i32, i32)> {
+        self.pointer
+    }
+}
@@ -0,0 +1,319 @@
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::{Keycode, Scancode};
+use toml::Value;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Stick positions closer to the centre than this are ignored
+const DEFAULT_DEADZONE: i16 = 8000;
+
+// A host key that can be bound to a hex key. Keycodes follow the active
+// layout (AZERTY 'A' is where QWERTY has 'Q'), scancodes are physical positions.
+// Axis bindings fire when the stick leaves the deadzone in the given direction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Binding {
+    Key(Keycode),
+    Scan(Scancode),
+    Button(Button),
+    Axis(Axis, bool),
+}
+
+// name without prefix, however the prefix is capitalized
+fn strip_prefix<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    match name.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&name[prefix.len()..]),
+        _ => None
+    }
+}
+
+impl Binding {
+    // "Up", "A", "Space" -> keycode, "scancode:A" -> scancode,
+    // "button:dpup" -> controller button, "axis:leftx-" -> stick pushed left
+    pub fn parse(name: &str) -> Result<Binding, String> {
+        if let Some(scan) = strip_prefix(name, "scancode:") {
+            return Scancode::from_name(scan)
+                .map(Binding::Scan)
+                .ok_or(format!("Unknown scancode {}", scan));
+        }
+
+        if let Some(button) = strip_prefix(name, "button:") {
+            return Button::from_string(button)
+                .map(Binding::Button)
+                .ok_or(format!("Unknown controller button {}", button));
+        }
+
+        if let Some(axis) = strip_prefix(name, "axis:") {
+            let (axis, positive) = match axis.chars().last() {
+                Some('+') => (&axis[..axis.len() - 1], true),
+                Some('-') => (&axis[..axis.len() - 1], false),
+                _ => return Err(format!("Axis {} needs a direction, + or -", axis))
+            };
+            return Axis::from_string(axis)
+                .map(|axis| Binding::Axis(axis, positive))
+                .ok_or(format!("Unknown controller axis {}", axis));
+        }
+
+        Keycode::from_name(name)
+            .map(Binding::Key)
+            .ok_or(format!("Unknown key {}", name))
+    }
+}
+
+// Where a keymap was loaded from, so it can be read again on request.
+#[derive(Clone, Debug)]
+struct Source {
+    path: PathBuf,
+    rom: Option<String>,
+    // the bindings the file was read on top of
+    base: Box<KeyMap>,
+}
+
+// Everything held on the host side for one frame, keyboard and controllers.
+#[derive(Default)]
+pub struct HostKeys {
+    pub keycodes: Vec<Keycode>,
+    pub scancodes: Vec<Scancode>,
+    pub buttons: Vec<Button>,
+    pub axes: Vec<(Axis, i16)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyMap {
+    bindings: [Vec<Binding>; 16],
+    deadzone: i16,
+    source: Option<Source>,
+}
+
+impl Default for KeyMap {
+    // the 1234/QWER/ASDF/ZXCV block, laid over the 123C/456D/789E/A0BF keypad.
+    // The d-pad and left stick cover the 2/4/6/8 cross most games steer with.
+    fn default() -> KeyMap {
+        let layout = [
+            (0x1, Keycode::Num1), (0x2, Keycode::Num2), (0x3, Keycode::Num3), (0xC, Keycode::Num4),
+            (0x4, Keycode::Q),    (0x5, Keycode::W),    (0x6, Keycode::E),    (0xD, Keycode::R),
+            (0x7, Keycode::A),    (0x8, Keycode::S),    (0x9, Keycode::D),    (0xE, Keycode::F),
+            (0xA, Keycode::Z),    (0x0, Keycode::X),    (0xB, Keycode::C),    (0xF, Keycode::V),
+        ];
+
+        let mut bindings: [Vec<Binding>; 16] = Default::default();
+        for &(hex, key) in layout.iter() {
+            bindings[hex].push(Binding::Key(key));
+        }
+
+        let pad = [
+            (0x2, Button::DPadUp, Axis::LeftY, false),
+            (0x8, Button::DPadDown, Axis::LeftY, true),
+            (0x4, Button::DPadLeft, Axis::LeftX, false),
+            (0x6, Button::DPadRight, Axis::LeftX, true),
+        ];
+        for &(hex, button, axis, positive) in pad.iter() {
+            bindings[hex].push(Binding::Button(button));
+            bindings[hex].push(Binding::Axis(axis, positive));
+        }
+        bindings[0x5].push(Binding::Button(Button::A));
+        bindings[0x0].push(Binding::Button(Button::B));
+
+        KeyMap { bindings, deadzone: DEFAULT_DEADZONE, source: None }
+    }
+}
+
+impl KeyMap {
+    // Reads a bindings file such as
+    //
+    //     [keys]
+    //     5 = ["W", "Up"]
+    //     0 = ["X", "scancode:Space"]
+    //
+    //     [controller]
+    //     deadzone = 8000
+    //
+    //     [rom.BRIX]
+    //     4 = ["Left", "button:dpleft", "axis:leftx-"]
+    //     6 = ["Right", "button:dpright", "axis:leftx+"]
+    //
+    // Hex keys that aren't listed keep the default layout, and the table
+    // under [rom.<name>] replaces bindings for that ROM only.
+    pub fn load(path: &Path, rom: Option<&str>) -> Result<KeyMap, String> {
+        KeyMap::default().load_over(path, rom)
+    }
+
+    // Like load, but keys the file doesn't mention keep these bindings
+    // instead of the defaults
+    pub fn load_over(&self, path: &Path, rom: Option<&str>) -> Result<KeyMap, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+        let mut keymap = self.parse_over(&text, rom)?;
+        keymap.source = Some(Source {
+            path: path.to_path_buf(),
+            rom: rom.map(String::from),
+            base: Box::new(self.clone()),
+        });
+        Ok(keymap)
+    }
+
+    pub fn parse(text: &str, rom: Option<&str>) -> Result<KeyMap, String> {
+        KeyMap::default().parse_over(text, rom)
+    }
+
+    pub fn parse_over(&self, text: &str, rom: Option<&str>) -> Result<KeyMap, String> {
+        let config = text.parse::<Value>().map_err(|e| e.to_string())?;
+        let mut keymap = self.clone();
+        keymap.source = None;
+
+        if let Some(keys) = config.get("keys") {
+            keymap.apply(keys)?;
+        }
+
+        if let Some(deadzone) = config.get("controller").and_then(|pad| pad.get("deadzone")) {
+            keymap.deadzone = deadzone.as_integer()
+                .filter(|&deadzone| deadzone >= 0 && deadzone <= i64::from(i16::MAX))
+                .ok_or("Controller deadzone must be between 0 and 32767")? as i16;
+        }
+
+        let overrides = rom.and_then(|rom| config.get("rom").and_then(|roms| roms.get(rom)));
+        if let Some(keys) = overrides {
+            keymap.apply(keys)?;
+        }
+
+        Ok(keymap)
+    }
+
+    // Loads the file this map came from again, picking up any edits.
+    pub fn reload(&self) -> Result<KeyMap, String> {
+        match self.source {
+            Some(ref source) => source.base.load_over(&source.path, source.rom.as_deref()),
+            None => Ok(self.clone()),
+        }
+    }
+
+    pub fn bindings(&self, hex: usize) -> &[Binding] {
+        &self.bindings[hex]
+    }
+
+    pub fn bind(&mut self, hex: usize, bindings: Vec<Binding>) {
+        self.bindings[hex] = bindings;
+    }
+
+    // Converts the host keys currently held into the state of the hex keypad.
+    pub fn pressed(&self, host: &HostKeys) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (hex, bindings) in self.bindings.iter().enumerate() {
+            keys[hex] = bindings.iter().any(|binding| match *binding {
+                Binding::Key(key) => host.keycodes.contains(&key),
+                Binding::Scan(scan) => host.scancodes.contains(&scan),
+                Binding::Button(button) => host.buttons.contains(&button),
+                Binding::Axis(axis, positive) => host.axes.iter().any(|&(held, value)| {
+                    // widen first, -32768 has no positive counterpart
+                    let value = i32::from(value);
+                    let deadzone = i32::from(self.deadzone);
+                    held == axis && if positive { value > deadzone } else { value < -deadzone }
+                }),
+            });
+        }
+        keys
+    }
+
+    // Binds the keys in a table such as the one under [keys]
+    pub fn apply(&mut self, table: &Value) -> Result<(), String> {
+        let table = table.as_table().ok_or("Key bindings must be a table")?;
+
+        for (hex, names) in table {
+            let hex = parse_hex_key(hex)?;
+            let names = match *names {
+                Value::String(ref name) => vec![name.as_str()],
+                Value::Array(ref names) => names.iter()
+                    .map(|name| name.as_str().ok_or(format!("Binding for key {:X} must be a string", hex)))
+                    .collect::<Result<Vec<&str>, String>>()?,
+                _ => return Err(format!("Bindings for key {:X} must be a string or a list", hex)),
+            };
+
+            let bindings = names.into_iter()
+                .map(Binding::parse)
+                .collect::<Result<Vec<Binding>, String>>()?;
+            self.bind(hex, bindings);
+        }
+        Ok(())
+    }
+}
+
+// "A", "a" and "0xA" all name hex key 0xA
+fn parse_hex_key(name: &str) -> Result<usize, String> {
+    let digits = name.trim_start_matches("0x").trim_start_matches("0X");
+    match usize::from_str_radix(digits, 16) {
+        Ok(hex) if hex < 16 => Ok(hex),
+        _ => Err(format!("{} is not a hex key (0-F)", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keymap::{Binding, HostKeys, KeyMap};
+    use sdl2::controller::{Axis, Button};
+    use sdl2::keyboard::{Keycode, Scancode};
+
+    #[test]
+    fn test_default_layout() {
+        let keymap = KeyMap::default();
+        assert_eq!(keymap.bindings(0xC), &[Binding::Key(Keycode::Num4)]);
+        assert_eq!(keymap.bindings(0x0), &[Binding::Key(Keycode::X), Binding::Button(Button::B)]);
+    }
+
+    #[test]
+    fn test_parse_multiple_bindings() {
+        let keymap = KeyMap::parse("[keys]\n5 = [\"Up\", \"scancode:W\"]\n0xa = \"Space\"", None).unwrap();
+        assert_eq!(keymap.bindings(0x5), &[Binding::Key(Keycode::Up), Binding::Scan(Scancode::W)]);
+        assert_eq!(keymap.bindings(0xA), &[Binding::Key(Keycode::Space)]);
+        assert_eq!(Binding::parse("ScanCode:W"), Ok(Binding::Scan(Scancode::W)));
+        // untouched keys keep their default
+        assert_eq!(keymap.bindings(0x1), &[Binding::Key(Keycode::Num1)]);
+    }
+
+    #[test]
+    fn test_rom_override() {
+        let config = "[keys]\n4 = \"Q\"\n[rom.BRIX]\n4 = \"Left\"";
+        let brix = KeyMap::parse(config, Some("BRIX")).unwrap();
+        assert_eq!(brix.bindings(0x4), &[Binding::Key(Keycode::Left)]);
+        let other = KeyMap::parse(config, Some("PONG")).unwrap();
+        assert_eq!(other.bindings(0x4), &[Binding::Key(Keycode::Q)]);
+    }
+
+    #[test]
+    fn test_pressed() {
+        let keymap = KeyMap::parse("[keys]\n6 = [\"Right\", \"scancode:D\"]", None).unwrap();
+        let keys = keymap.pressed(&HostKeys { scancodes: vec![Scancode::D], ..Default::default() });
+        assert!(keys[0x6]);
+        let keys = keymap.pressed(&HostKeys { keycodes: vec![Keycode::Right, Keycode::X], ..Default::default() });
+        assert!(keys[0x6] && keys[0x0]);
+    }
+
+    #[test]
+    fn test_parse_controller() {
+        let keymap = KeyMap::parse("[keys]\n4 = [\"button:dpleft\", \"axis:leftx-\"]", None).unwrap();
+        assert_eq!(keymap.bindings(0x4), &[Binding::Button(Button::DPadLeft), Binding::Axis(Axis::LeftX, false)]);
+        assert!(KeyMap::parse("[keys]\n4 = \"axis:leftx\"", None).is_err());
+        assert!(KeyMap::parse("[keys]\n4 = \"button:turbo\"", None).is_err());
+    }
+
+    #[test]
+    fn test_deadzone() {
+        let keymap = KeyMap::parse("[controller]\ndeadzone = 10000", None).unwrap();
+        let keys = keymap.pressed(&HostKeys { axes: vec![(Axis::LeftX, -9000)], ..Default::default() });
+        assert!(!keys[0x4]);
+        let keys = keymap.pressed(&HostKeys { axes: vec![(Axis::LeftX, -32768)], ..Default::default() });
+        assert!(keys[0x4] && !keys[0x6]);
+        let keys = keymap.pressed(&HostKeys { buttons: vec![Button::DPadDown], ..Default::default() });
+        assert!(keys[0x8]);
+    }
+
+    #[test]
+    fn test_bad_config() {
+        assert!(KeyMap::parse("[keys]\n10 = \"A\"", None).is_err());
+        assert!(KeyMap::parse("[keys]\n1 = \"NotAKey\"", None).is_err());
+        assert!(KeyMap::parse("[keys]\n1 = 5", None).is_err());
+    }
+}
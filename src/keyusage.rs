@@ -0,0 +1,167 @@
+use instruction::convert_platform_op;
+use instruction::Instruction::WaitKey;
+use platform::Platform;
+
+// Which hex keys a game reads, found by looking through the ROM before it
+// runs and by watching which keys it tests while it does.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyUsage {
+    keys: [bool; 16],
+    // the game waits for a key press (Fx0A), which takes any key
+    any_key: bool,
+}
+
+// Host key names of the default layout, indexed by hex key
+const DEFAULT_NAMES: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+// The cross most games steer with, and the arrow keys that suit it
+const CROSS: [(u8, &str); 4] = [(0x2, "Up"), (0x4, "Left"), (0x6, "Right"), (0x8, "Down")];
+
+impl KeyUsage {
+    // Key tests usually load the key into a register right before testing it:
+    //
+    //     6x05    V[x] = 5
+    //     Ex9E    skip if key V[x] is pressed
+    //
+    // so those pairs are looked for at every byte, as code isn't always
+    // aligned. Keys picked at run time are only seen by `observe`. Opcodes
+    // are read the way `platform` reads them, when it's known, so the
+    // address after F000 or MegaChip's 01nn isn't taken for an instruction.
+    pub fn scan(rom: &[u8], platform: Option<Platform>) -> KeyUsage {
+        let mut usage = KeyUsage::default();
+        let op = |at: usize| (rom[at] as u16) << 8 | rom[at + 1] as u16;
+        let address_at = |at: usize| at >= 2 && convert_platform_op(op(at - 2), platform).length() == 4;
+
+        for at in 0..rom.len().saturating_sub(3) {
+            if address_at(at) {
+                continue;
+            }
+            let (set, test) = (op(at), op(at + 2));
+            let reg = (test >> 8) & 0xF;
+            let is_test = test & 0xF0FF == 0xE09E || test & 0xF0FF == 0xE0A1;
+            if set >> 12 == 0x6 && (set >> 8) & 0xF == reg && is_test {
+                usage.keys[(set & 0xF) as usize] = true;
+            }
+        }
+
+        let mut at = 0;
+        while at + 1 < rom.len() {
+            let instruction = convert_platform_op(op(at), platform);
+            if let WaitKey {..} = instruction {
+                usage.any_key = true;
+            }
+            at += instruction.length();
+        }
+        usage
+    }
+
+    // Adds the keys the CPU has tested so far, true if any were new
+    pub fn observe(&mut self, tested: [bool; 16]) -> bool {
+        let mut new = false;
+        for (known, &tested) in self.keys.iter_mut().zip(tested.iter()) {
+            if tested && !*known {
+                *known = true;
+                new = true;
+            }
+        }
+        new
+    }
+
+    pub fn keys(&self) -> Vec<u8> {
+        (0..16).filter(|&key| self.keys[key as usize]).collect()
+    }
+
+    // "This game uses keys 4, 5 and 6", None if nothing was found
+    pub fn hint(&self) -> Option<String> {
+        let keys: Vec<String> = self.keys().iter().map(|key| format!("{:X}", key)).collect();
+        let listed = match keys.len() {
+            0 if self.any_key => return Some(String::from("This game waits for any key")),
+            0 => return None,
+            1 => format!("This game uses key {}", keys[0]),
+            n => format!("This game uses keys {} and {}", keys[..n - 1].join(", "), keys[n - 1])
+        };
+        Some(if self.any_key { format!("{}, or any key to start", listed) } else { listed })
+    }
+
+    // A [rom.NAME] table for the bindings file covering just the keys the
+    // game uses: arrows for the 2/4/6/8 cross, space for the first other
+    // key, and the default layout for the rest.
+    pub fn template(&self, rom: &str) -> String {
+        let arrows = CROSS.iter().filter(|&&(key, _)| self.keys[key as usize]).count() >= 2;
+        let mut space = false;
+        let mut table = format!("[rom.{}]\n", rom);
+        for key in self.keys() {
+            let name = match CROSS.iter().find(|&&(cross, _)| arrows && cross == key) {
+                Some(&(_, arrow)) => arrow,
+                None if !space => {
+                    space = true;
+                    "Space"
+                },
+                None => DEFAULT_NAMES[key as usize]
+            };
+            table.push_str(&format!("{:X} = [\"{}\"]\n", key, name));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keyusage::KeyUsage;
+    use platform::Platform;
+
+    #[test]
+    fn test_scan_finds_tested_keys() {
+        let rom = [
+            0x64, 0x04, 0xE4, 0xA1, // V4 = 4, skip unless key 4
+            0x00, // knocks what follows off alignment
+            0x63, 0x06, 0xE3, 0x9E, // V3 = 6, skip if key 6
+            0x65, 0x05, 0xE6, 0x9E, // different registers, not a pair
+        ];
+        let usage = KeyUsage::scan(&rom, None);
+        assert_eq!(usage.keys(), vec![0x4, 0x6]);
+        assert!(!usage.any_key);
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let usage = KeyUsage::scan(&[0x00, 0xE0, 0xF2, 0x0A], None);
+        assert_eq!(usage.hint(), Some(String::from("This game waits for any key")));
+    }
+
+    #[test]
+    fn test_long_addresses_are_not_code() {
+        // I = 0xF20A, which isn't Fx0A, then I = 0x006404 on MegaChip,
+        // which isn't a key test either
+        let rom = [0xF0, 0x00, 0xF2, 0x0A, 0x01, 0x00, 0x64, 0x04, 0xE4, 0xA1];
+        assert_eq!(KeyUsage::scan(&rom, Some(Platform::MegaChip)), KeyUsage::default());
+        let usage = KeyUsage::scan(&rom, None);
+        assert!(!usage.any_key);
+        assert_eq!(usage.keys(), vec![0x4]);
+    }
+
+    #[test]
+    fn test_observe() {
+        let mut usage = KeyUsage::scan(&[0x64, 0x04, 0xE4, 0xA1], None);
+        let mut tested = [false; 16];
+        tested[4] = true;
+        assert!(!usage.observe(tested));
+        tested[0xC] = true;
+        assert!(usage.observe(tested));
+        assert_eq!(usage.keys(), vec![0x4, 0xC]);
+    }
+
+    #[test]
+    fn test_hint_and_template() {
+        let mut tested = [false; 16];
+        tested[4] = true;
+        tested[5] = true;
+        tested[6] = true;
+        let mut usage = KeyUsage::default();
+        usage.observe(tested);
+        assert_eq!(usage.hint(), Some(String::from("This game uses keys 4, 5 and 6")));
+        assert_eq!(usage.template("BRIX"), "[rom.BRIX]\n4 = [\"Left\"]\n5 = [\"Space\"]\n6 = [\"Right\"]\n");
+    }
+}
@@ -1,9 +1,35 @@
+pub mod cartridge;
+pub mod cdp1802;
 pub mod cpu;
+pub mod detect;
 pub mod instruction;
+pub mod framebuffer;
+pub mod gamedb;
+pub mod frontend;
 pub mod graphics;
 pub mod keyboard;
+pub mod keymap;
+pub mod keyusage;
+pub mod octo;
+pub mod palette;
+pub mod platform;
+pub mod recompiler;
+pub mod recorder;
+pub mod romfile;
+pub mod rplflags;
+pub mod screenshot;
+pub mod sha1;
+pub mod speaker;
+pub mod terminal;
+pub mod vip;
 extern crate sdl2;
 extern crate rand;
+extern crate toml;
+extern crate png;
+extern crate gif;
+extern crate miniz_oxide;
+#[macro_use]
+extern crate crossterm;
 
 pub const CHIP8_HEIGHT: usize = 32;
 pub const CHIP8_WIDTH: usize = 64;
\ No newline at end of file
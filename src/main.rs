@@ -1,13 +1,78 @@
 extern crate chip8_cpu;
 
+use chip8_cpu::cartridge::{self, Cartridge};
 use chip8_cpu::cpu::Cpu;
+use chip8_cpu::detect;
+use chip8_cpu::framebuffer::Screen;
+use chip8_cpu::frontend::{scale_for, Hotkey, Input, Renderer, Status};
+use chip8_cpu::gamedb::{GameDb, GameInfo};
 use chip8_cpu::graphics::Graphics;
+use chip8_cpu::graphics::persistence::Persistence;
+use chip8_cpu::graphics::scaler::{ScaleOptions, Upscale};
 use chip8_cpu::keyboard::Keyboard;
+use chip8_cpu::keymap::KeyMap;
+use chip8_cpu::keyusage::KeyUsage;
+use chip8_cpu::palette::Palette;
+use chip8_cpu::platform::{Platform, Quirks};
+use chip8_cpu::recorder::{Recorder, FRAMES_PER_SECOND};
+use chip8_cpu::romfile::{self, Zip};
+use chip8_cpu::rplflags::FlagFile;
+use chip8_cpu::screenshot;
+use chip8_cpu::sha1;
+use chip8_cpu::speaker::{Speaker, SAMPLE_RATE};
+use chip8_cpu::terminal::{CellMode, Terminal};
+use chip8_cpu::vip::Vip;
 
-use std::io::Read;
-use std::fs::File;
+use std::env;
+use std::io::{self, Read, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-fn read_rom(filename: &str, rom: &mut Vec<u8>) {
+const DEFAULT_KEYS_FILE: &str = "keys.toml";
+const DEFAULT_GAMES_FILE: &str = "games.toml";
+
+// 600 instructions a second, roughly what the COSMAC VIP managed
+const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+struct Options {
+    filename: String,
+    // which file to run when the ROM is a ZIP archive
+    entry: Option<String>,
+    keys_file: Option<String>,
+    // None runs in an SDL window
+    terminal: Option<CellMode>,
+    // None leaves it to the game database, then the default
+    instructions_per_frame: Option<u32>,
+    // run as fast as the COSMAC VIP did rather than a set number of
+    // instructions a frame
+    vip_timing: bool,
+    // overrides what the game database says
+    quirks: Option<Quirks>,
+    // the machine the ROM was written for, over the game database and detection
+    platform: Option<Platform>,
+    // read on top of the built in game database
+    games_file: Option<String>,
+    // print what's known about the ROM and quit
+    rom_info: bool,
+    palette: Option<String>,
+    persistence: Persistence,
+    scaling: ScaleOptions,
+    fullscreen: bool,
+    // show the clickable hex keypad next to the game
+    keypad: bool,
+    // print bindings for the keys the game used on the way out
+    keys_template: bool,
+    // start recording straight away, to a .gif or a directory of PNGs
+    record: Option<String>,
+    // run on the emulated COSMAC VIP, with its monitor ROM and the CHIP-8
+    // interpreter to load at 0x0000
+    vip_monitor: Option<String>,
+    vip_interpreter: Option<String>
+}
+
+fn read_rom(filename: &str, entry: Option<&str>, rom: &mut Vec<u8>) {
     let mut rom_data = match File::open(filename) {
         Ok(data) => data,
         Err(_) => {
@@ -16,36 +81,568 @@ fn read_rom(filename: &str, rom: &mut Vec<u8>) {
     };
 
     rom_data.read_to_end(rom).unwrap();
+
+    if romfile::is_zip(rom) {
+        let contents = {
+            let zip = Zip::parse(rom).unwrap_or_else(|e| panic!("Could not read {}: {}", filename, e));
+            let name = match entry {
+                Some(name) => String::from(name),
+                None => choose_entry(&zip, filename)
+            };
+            zip.read(&name).unwrap_or_else(|e| panic!("Could not read {}: {}", filename, e))
+        };
+        *rom = contents;
+    }
+    if let Some(decoded) = romfile::decode_text(rom) {
+        *rom = decoded.unwrap_or_else(|e| panic!("Could not read {}: {}", filename, e));
+    }
+}
+
+// The archive's only ROM, or the one picked from a list
+fn choose_entry(zip: &Zip, filename: &str) -> String {
+    let roms = zip.roms();
+    match roms.len() {
+        0 => panic!("{} has no ROMs in it, pick a file with --entry: {}", filename, zip.names().join(", ")),
+        1 => return String::from(roms[0]),
+        _ => ()
+    }
+
+    println!("{} holds more than one ROM:", filename);
+    for (number, name) in roms.iter().enumerate() {
+        println!("{:3}. {}", number + 1, name);
+    }
+    loop {
+        print!("Which one? ");
+        io::stdout().flush().unwrap();
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            panic!("No ROM picked, use --entry to choose one");
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(number) if (1..=roms.len()).contains(&number) => return String::from(roms[number - 1]),
+            _ => println!("Pick a number from 1 to {}", roms.len())
+        }
+    }
+}
+
+// chip8 [--keys FILE] [--tui | --braille] [--ipf N | --vip-timing] [--palette NAME | COLORS]
+//       [--filter off | blend | phosphor[:DECAY]] [--scaler nearest | scale2x | scale3x]
+//       [--scanlines] [--grid] [--integer-scale] [--fullscreen] [--keypad]
+//       [--keys-template] [--record FILE.gif | DIR] [--games FILE]
+//       [--quirks chip8 | schip | xochip | legacy] [--platform NAME] [--rom-info] [--entry FILE]
+//       [--vip-monitor FILE --vip-interpreter FILE] [ROM]
+fn parse_args() -> Options {
+    let mut options = Options {
+        filename: String::from("roms/BRIX"),
+        entry: None,
+        keys_file: None,
+        terminal: None,
+        instructions_per_frame: None,
+        vip_timing: false,
+        quirks: None,
+        platform: None,
+        games_file: None,
+        rom_info: false,
+        palette: None,
+        persistence: Persistence::Off,
+        scaling: ScaleOptions::default(),
+        fullscreen: false,
+        keypad: false,
+        keys_template: false,
+        record: None,
+        vip_monitor: None,
+        vip_interpreter: None
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keys" => options.keys_file = Some(args.next().expect("--keys needs a file")),
+            "--tui" => options.terminal = Some(CellMode::HalfBlock),
+            "--braille" => options.terminal = Some(CellMode::Braille),
+            "--ipf" => {
+                options.instructions_per_frame = Some(args.next()
+                    .and_then(|ipf| ipf.parse().ok())
+                    .expect("--ipf needs a number of instructions"))
+            },
+            "--vip-timing" => options.vip_timing = true,
+            "--quirks" => {
+                let preset = args.next().expect("--quirks needs chip8, schip, xochip or legacy");
+                options.quirks = Some(Quirks::preset(&preset).unwrap_or_else(|e| panic!("{}", e)));
+            },
+            "--platform" => {
+                let name = args.next().expect("--platform needs chip8, schip, xochip, eti660, dream6800, chip8x or megachip");
+                options.platform = Some(Platform::parse(&name).unwrap_or_else(|e| panic!("{}", e)));
+            },
+            "--entry" => options.entry = Some(args.next().expect("--entry needs a file in the archive")),
+            "--games" => options.games_file = Some(args.next().expect("--games needs a file")),
+            "--rom-info" => options.rom_info = true,
+            "--palette" => options.palette = Some(args.next().expect("--palette needs a name or colors")),
+            "--filter" => {
+                let filter = args.next().expect("--filter needs off, blend or phosphor");
+                options.persistence = Persistence::parse(&filter).unwrap_or_else(|e| panic!("{}", e));
+            },
+            "--scaler" => {
+                let scaler = args.next().expect("--scaler needs nearest, scale2x or scale3x");
+                options.scaling.upscale = Upscale::parse(&scaler).unwrap_or_else(|e| panic!("{}", e));
+            },
+            "--scanlines" => options.scaling.scanlines = true,
+            "--grid" => options.scaling.grid = true,
+            "--integer-scale" => options.scaling.integer = true,
+            "--fullscreen" => options.fullscreen = true,
+            "--keypad" => options.keypad = true,
+            "--keys-template" => options.keys_template = true,
+            "--record" => options.record = Some(args.next().expect("--record needs a file or directory")),
+            "--vip-monitor" => options.vip_monitor = Some(args.next().expect("--vip-monitor needs a file")),
+            "--vip-interpreter" => options.vip_interpreter = Some(args.next().expect("--vip-interpreter needs a file")),
+            _ => options.filename = arg
+        }
+    }
+
+    if options.vip_timing && options.instructions_per_frame.is_some() {
+        println!("--vip-timing sets the speed itself, --ipf is ignored");
+    }
+    // the monitor is only any use to the VIP, which needs an interpreter to run
+    if options.vip_monitor.is_some() && options.vip_interpreter.is_none() {
+        panic!("--vip-monitor needs a CHIP-8 interpreter from --vip-interpreter too");
+    }
+
+    // fall back to a keys.toml next to where we were started
+    if options.keys_file.is_none() && Path::new(DEFAULT_KEYS_FILE).exists() {
+        options.keys_file = Some(String::from(DEFAULT_KEYS_FILE));
+    }
+    if options.games_file.is_none() && Path::new(DEFAULT_GAMES_FILE).exists() {
+        options.games_file = Some(String::from(DEFAULT_GAMES_FILE));
+    }
+
+    options
+}
+
+// The bindings file wins over the game database
+fn load_keymap(keys_file: Option<String>, filename: &str, game: Option<&GameInfo>) -> KeyMap {
+    let mut keymap = KeyMap::default();
+    if let Some(keys) = game.and_then(|game| game.keys.as_ref()) {
+        keymap.apply(keys).unwrap_or_else(|e| panic!("Bad key bindings in the game database: {}", e));
+    }
+
+    let keys_file = match keys_file {
+        Some(file) => file,
+        None => return keymap
+    };
+
+    // per-ROM overrides are looked up by the ROM's file name, e.g. [rom.BRIX]
+    let rom_name = Path::new(filename).file_stem().and_then(|name| name.to_str());
+    match keymap.load_over(Path::new(&keys_file), rom_name) {
+        Ok(keymap) => keymap,
+        Err(e) => panic!("Bad key bindings: {}", e)
+    }
+}
+
+// --palette wins over [display] in the config file, which wins over the game database
+fn load_palette(options: &Options, game: Option<&GameInfo>) -> Palette {
+    if let Some(ref spec) = options.palette {
+        return Palette::parse(spec).unwrap_or_else(|e| panic!("Bad palette: {}", e));
+    }
+
+    let config = options.keys_file.as_ref().and_then(|file| {
+        let mut text = String::new();
+        File::open(file).and_then(|mut file| file.read_to_string(&mut text)).ok()?;
+        text.parse().ok()
+    });
+    match config.map(|config| Palette::from_config(&config)) {
+        Some(Ok(Some(palette))) => palette,
+        Some(Err(e)) => panic!("Bad palette: {}", e),
+        _ => game.and_then(|game| game.palette.clone()).unwrap_or_default()
+    }
+}
+
+// The presets in order, with a custom palette slotted in first
+fn next_palette(current: &Palette) -> Palette {
+    let mut palettes = Palette::presets();
+    if !palettes.contains(current) {
+        palettes.insert(0, current.clone());
+    }
+    let index = palettes.iter().position(|palette| palette == current).unwrap_or(0);
+    palettes[(index + 1) % palettes.len()].clone()
+}
+
+// <rom>-<unix time>, in the working directory
+fn output_stem(filename: &str) -> PathBuf {
+    let rom = Path::new(filename).file_stem().and_then(|name| name.to_str()).unwrap_or("chip8");
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    PathBuf::from(format!("{}-{}", rom, time))
+}
+
+// Starts a recording, or stops the one that's running.
+fn toggle_recording<R: Renderer>(recorder: &mut Option<Recorder>, path: &Path, screen: &Screen, frontend: &mut R) {
+    match recorder.take() {
+        Some(recording) => match recording.finish() {
+            Ok(_) => frontend.message("Recording stopped"),
+            Err(e) => frontend.message(&format!("Could not finish recording: {}", e))
+        },
+        None => {
+            let started = if path.extension().is_some_and(|ext| ext == "gif") {
+                Recorder::gif(path, (screen.width(), screen.height()), frontend.scale(), frontend.palette().colors())
+            } else {
+                Recorder::png_sequence(path, frontend.scale(), frontend.palette().colors())
+            };
+            match started {
+                Ok(recording) => {
+                    frontend.message(&format!("Recording to {}", path.display()));
+                    *recorder = Some(recording);
+                },
+                Err(e) => frontend.message(&format!("Could not start recording: {}", e))
+            }
+        }
+    }
+}
+
+// The hex keys held this frame, with any keypad button the mouse is on.
+// None once the user has quit.
+fn read_keys<F: Renderer + Input>(frontend: &mut F) -> Option<[bool; 16]> {
+    let mut keys = frontend.get_keys()?;
+    if let Some(key) = frontend.pointer().and_then(|(x, y)| frontend.keypad_at(x, y)) {
+        keys[key as usize] = true;
+    }
+    frontend.set_keys(keys);
+    Some(keys)
+}
+
+// Does what the hotkeys pressed since last frame ask for, whichever machine
+// is running. True if the screen needs drawing again.
+fn handle_hotkeys<F: Renderer + Input>(frontend: &mut F, screen: &Screen, recorder: &mut Option<Recorder>,
+                                       status: &mut Status, options: &Options) -> bool {
+    let mut drawn = false;
+    for hotkey in frontend.hotkeys() {
+        match hotkey {
+            Hotkey::Screenshot => {
+                let stem = output_stem(&options.filename);
+                let scale = scale_for(frontend.scale(), screen.width());
+                match screenshot::save(&stem, screen, scale, frontend.palette().colors()) {
+                    Ok(_) => frontend.message(&format!("Saved screenshot {}.png", stem.display())),
+                    Err(e) => frontend.message(&format!("Could not save screenshot: {}", e))
+                }
+            },
+            Hotkey::RecordGif => {
+                let path = output_stem(&options.filename).with_extension("gif");
+                toggle_recording(recorder, &path, screen, frontend);
+            },
+            Hotkey::RecordFrames => {
+                let path = output_stem(&options.filename);
+                toggle_recording(recorder, &path, screen, frontend);
+            },
+            Hotkey::NextPalette => {
+                // a recording keeps the palette it started with
+                let palette = next_palette(&frontend.palette());
+                frontend.message(&format!("Palette: {}", palette.name));
+                frontend.set_palette(palette);
+                drawn = true;
+            },
+            Hotkey::Fullscreen => {
+                frontend.toggle_fullscreen();
+                drawn = true;
+            },
+            Hotkey::Pause => {
+                status.paused = !status.paused;
+                frontend.set_status(*status);
+            },
+            Hotkey::ToggleOsd => {
+                frontend.toggle_osd();
+                drawn = true;
+            }
+        }
+    }
+    drawn
+}
+
+// Adds the screen to the recording, if there is one, and drops the
+// recording if that fails
+fn record_frame<R: Renderer>(recorder: &mut Option<Recorder>, screen: &Screen, frontend: &mut R) {
+    if let Some(recording) = recorder.as_mut() {
+        if let Err(e) = recording.frame(screen) {
+            frontend.message(&format!("Recording failed: {}", e));
+            *recorder = None;
+        }
+    }
+}
+
+fn run<F: Renderer + Input>(processor: &mut Cpu, frontend: &mut F, usage: &mut KeyUsage, flag_file: Option<&FlagFile>,
+                            mut speaker: Option<&mut Speaker>, options: &Options) {
+    let frame_time = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    let mut recorder = None;
+    if let Some(ref path) = options.record {
+        toggle_recording(&mut recorder, Path::new(path), processor.screen(), frontend);
+    }
+    if options.fullscreen {
+        frontend.toggle_fullscreen();
+    }
+    if let Some(title) = processor.game().and_then(GameInfo::describe) {
+        frontend.message(&title);
+    }
+    if let Some(detection) = processor.detection() {
+        frontend.message(&detection.describe());
+    }
+    if let Some(hint) = usage.hint() {
+        frontend.message(&hint);
+    }
+    if let Some(file) = flag_file {
+        match file.load() {
+            Ok(Some(flags)) => processor.set_flags(flags),
+            Ok(None) => (),
+            Err(e) => frontend.message(&format!("Could not read the saved flags: {}", e))
+        }
+    }
+    let instructions_per_frame = options.instructions_per_frame.unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME);
+
+    let mut status = Status::default();
+    // frames and instructions run since the speed was last worked out
+    let (mut counted_since, mut frames, mut instructions) = (Instant::now(), 0, 0);
+
+    'running: loop {
+        let frame_start = Instant::now();
+
+        let keys = match read_keys(frontend) {
+            Some(keys) => keys,
+            None => break
+        };
+        let mut drawn = handle_hotkeys(frontend, processor.screen(), &mut recorder, &mut status, options);
+
+        if !status.paused {
+            if options.vip_timing {
+                let frame = processor.run_vip_frame(keys);
+                if frame.finished {
+                    break 'running;
+                }
+                instructions += frame.instructions;
+            } else {
+                for _ in 0..instructions_per_frame {
+                    let state = processor.step(keys);
+                    if state.finished {
+                        break 'running;
+                    }
+                    //processor.dump();
+                }
+                instructions += instructions_per_frame;
+            }
+            processor.tick_timers();
+            // MegaChip's samples, a frame at a time
+            if let (Some(speaker), true) = (speaker.as_mut(), processor.playing_sample()) {
+                let mut samples = vec![0; (SAMPLE_RATE / FRAMES_PER_SECOND) as usize];
+                processor.sample_audio(&mut samples, SAMPLE_RATE);
+                speaker.play(&samples);
+            }
+            // high scores and the like, kept for next time
+            if let (Some(flags), Some(file)) = (processor.take_saved_flags(), flag_file) {
+                if let Err(e) = file.save(flags) {
+                    frontend.message(&format!("Could not save the flags: {}", e));
+                }
+            }
+            // keys picked at run time only turn up once they're tested
+            if usage.observe(processor.tested_keys()) {
+                if let Some(hint) = usage.hint() {
+                    frontend.message(&hint);
+                }
+            }
+            frames += 1;
+        }
+
+        let elapsed = counted_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            status.fps = frames as f64 / elapsed.as_secs_f64();
+            status.ips = instructions as f64 / elapsed.as_secs_f64();
+            frontend.set_status(status);
+            counted_since = Instant::now();
+            frames = 0;
+            instructions = 0;
+        }
+
+        // however many sprites went out this frame, the screen is only presented once
+        if processor.take_dirty() {
+            drawn = true;
+        }
+        if drawn || frontend.fading() {
+            frontend.draw(processor.screen());
+        }
+        // a pause doesn't end up in the recording
+        if !status.paused {
+            record_frame(&mut recorder, processor.screen(), frontend);
+        }
+
+        if let Some(rest) = frame_time.checked_sub(frame_start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+
+    if let Some(recording) = recorder {
+        if let Err(e) = recording.finish() {
+            frontend.message(&format!("Could not finish recording: {}", e));
+        }
+    }
+}
+
+// The same as run, but on the emulated COSMAC VIP, which runs the CHIP-8
+// interpreter itself and so has no quirks, speed or flags to look after
+fn run_vip<F: Renderer + Input>(vip: &mut Vip, frontend: &mut F, mut speaker: Option<&mut Speaker>, options: &Options) {
+    let frame_time = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    let mut recorder = None;
+    let mut screen = vip.screen();
+    if let Some(ref path) = options.record {
+        toggle_recording(&mut recorder, Path::new(path), &screen, frontend);
+    }
+    if options.fullscreen {
+        frontend.toggle_fullscreen();
+    }
+    let mut status = Status::default();
+
+    loop {
+        let frame_start = Instant::now();
+
+        let keys = match read_keys(frontend) {
+            Some(keys) => keys,
+            None => break
+        };
+        let mut drawn = handle_hotkeys(frontend, &screen, &mut recorder, &mut status, options);
+
+        if !status.paused {
+            vip.run_frame(keys);
+            // the beeper, for as long as Q is on at the end of a frame
+            if let (Some(speaker), true) = (speaker.as_mut(), vip.tone()) {
+                speaker.beep();
+            }
+            let next = vip.screen();
+            drawn |= next != screen;
+            screen = next;
+        }
+        if drawn || frontend.fading() {
+            frontend.draw(&screen);
+        }
+        if !status.paused {
+            record_frame(&mut recorder, &screen, frontend);
+        }
+
+        if let Some(rest) = frame_time.checked_sub(frame_start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+
+    if let Some(recording) = recorder {
+        if let Err(e) = recording.finish() {
+            frontend.message(&format!("Could not finish recording: {}", e));
+        }
+    }
+}
+
+// A VIP with the monitor, the CHIP-8 interpreter and the game in memory
+fn load_vip(monitor: Option<&str>, interpreter: &str, rom: &[u8]) -> Vip {
+    let read = |file: &str| fs::read(file).unwrap_or_else(|e| panic!("Could not read {}: {}", file, e));
+    let monitor = monitor.expect("--vip-interpreter needs the VIP's monitor ROM from --vip-monitor too");
+    let mut vip = Vip::new(&read(monitor)).unwrap_or_else(|e| panic!("{}", e));
+    vip.load(0x000, &read(interpreter)).unwrap_or_else(|e| panic!("Could not load the interpreter: {}", e));
+    vip.load(0x200, rom).unwrap_or_else(|e| panic!("Could not load the ROM: {}", e));
+    vip
+}
+
+// What --rom-info prints
+fn print_rom_info(rom: &[u8], game: Option<&GameInfo>, platform: Option<Platform>, usage: &KeyUsage) {
+    println!("SHA-1: {}", sha1::hex_digest(rom));
+    match game {
+        Some(game) => {
+            println!("Game: {}", game.describe().unwrap_or_else(|| String::from("untitled")));
+            if let Some(platform) = game.platform {
+                println!("Platform: {}", platform.name());
+            }
+            if let Some(ipf) = game.instructions_per_frame {
+                println!("Instructions per frame: {}", ipf);
+            }
+        },
+        None => {
+            println!("Not in the game database");
+            println!("{}", detect::detect(rom, platform).describe());
+        }
+    }
+    if let Some(hint) = usage.hint() {
+        println!("{}", hint);
+    }
 }
 
 fn main() {
-    // TODO: uncouple from main
-    let context = sdl2::init().unwrap();
-    let mut gfx = Graphics::new(&context);
-    let mut kb = Keyboard::new(&context);
+    let mut options = parse_args();
+
+    let mut games = GameDb::builtin();
+    if let Some(ref file) = options.games_file {
+        games.extend(GameDb::load(Path::new(file)).unwrap_or_else(|e| panic!("Bad game database: {}", e)));
+    }
 
     let mut processor = Cpu::new();
+    let mut usage;
+    let flag_file;
+    let mut vip = None;
 
-    let filename = "roms/BRIX";
     {
         let mut rom = Vec::new();
 
-        read_rom(filename, &mut rom);
+        read_rom(&options.filename, options.entry.as_deref(), &mut rom);
+        // Octo cartridges hold the program's source and the settings it wants
+        if cartridge::is_cartridge(&rom) {
+            let cartridge = Cartridge::decode(&rom)
+                .unwrap_or_else(|e| panic!("Could not load the cartridge {}: {}", options.filename, e));
+            rom = cartridge.rom;
+            games.insert(&rom, cartridge.game);
+        }
+        let platform = options.platform.or_else(|| games.lookup(&rom).and_then(|game| game.platform));
+        usage = KeyUsage::scan(&rom, platform);
+        if options.rom_info {
+            print_rom_info(&rom, games.lookup(&rom), options.platform, &usage);
+            return;
+        }
+        flag_file = FlagFile::for_rom(&rom);
+        if let Some(ref interpreter) = options.vip_interpreter {
+            vip = Some(load_vip(options.vip_monitor.as_deref(), interpreter, &rom));
+        }
+        processor.set_game_db(games);
+        if let Some(platform) = options.platform {
+            processor.set_platform(platform);
+        }
         processor.load_rom(rom);
     }
 
-    loop {
-        let keys = kb.get_keys();
-        if keys.is_none() {
-            break;
-        }
-        let state = processor.step(keys.unwrap());
-        if state.finished {
-            break;
-        }
-        if state.drawn {
-            gfx.draw(&state.screen);
+    // the command line wins over the game database and detection
+    if let Some(quirks) = options.quirks {
+        processor.set_quirks(quirks);
+    }
+    let game = processor.game().cloned();
+    options.instructions_per_frame = options.instructions_per_frame
+        .or_else(|| game.as_ref().and_then(|game| game.instructions_per_frame));
+    let keymap = load_keymap(options.keys_file.clone(), &options.filename, game.as_ref());
+    let palette = load_palette(&options, game.as_ref());
+
+    match options.terminal {
+        Some(mode) => {
+            let mut term = Terminal::new(keymap, mode, palette).expect("Could not set up the terminal");
+            match vip {
+                Some(ref mut vip) => run_vip(vip, &mut term, None, &options),
+                None => run(&mut processor, &mut term, &mut usage, flag_file.as_ref(), None, &options)
+            }
+        },
+        None => {
+            // TODO: uncouple from main
+            let context = sdl2::init().unwrap();
+            let mut gfx = Graphics::new(&context, palette, options.persistence, options.scaling);
+            if options.keypad {
+                gfx.show_keypad();
+            }
+            let kb = Keyboard::new(&context, keymap);
+            // no sound card just means no sound
+            let mut speaker = Speaker::new(&context).map_err(|e| println!("No sound: {}", e)).ok();
+            match vip {
+                Some(ref mut vip) => run_vip(vip, &mut (gfx, kb), speaker.as_mut(), &options),
+                None => run(&mut processor, &mut (gfx, kb), &mut usage, flag_file.as_ref(), speaker.as_mut(), &options)
+            }
         }
-        //processor.dump();
+    }
+
+    if options.keys_template {
+        let rom = Path::new(&options.filename).file_stem().and_then(|name| name.to_str()).unwrap_or("chip8");
+        print!("{}", usage.template(rom));
     }
 }
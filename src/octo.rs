@@ -0,0 +1,831 @@
+use std::collections::{HashMap, VecDeque};
+
+// Where programs are loaded, and where Octo starts assembling
+const START: usize = 0x200;
+
+// More expansions than any real program needs, so a macro that calls itself
+// stops with an error instead of growing forever
+const MAX_MACRO_CALLS: usize = 10000;
+
+// An assembler for Octo, the language Octo cartridges carry their programs
+// in. It covers the instructions of CHIP-8, SUPER-CHIP and XO-CHIP, the
+// structured control flow, and the :const/:alias/:calc/:macro family of
+// directives. Text modes (:stringmode) aren't supported.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler::new(source);
+    // room for the jump to main, filled in once main is known
+    assembler.emit(0)?;
+    while !assembler.tokens.is_empty() {
+        let line = assembler.tokens[0].1;
+        assembler.statement().map_err(|e| format!("Line {}: {}", line, e))?;
+    }
+    assembler.finish()
+}
+
+type Token = (String, usize);
+
+// Parts of the program written before the label they use was defined
+#[derive(Clone, Copy)]
+enum Fixup {
+    // the low 12 bits of an instruction
+    Address,
+    // both bytes of a 16 bit word
+    Long,
+    // the byte :unpack loads into v0, below a nibble of its own
+    High(u8),
+    // the byte :unpack long loads into v0, all 8 bits of the address
+    HighByte,
+    // the byte :unpack loads into v1
+    Low,
+}
+
+// The skips an `if` or `while` emits, by whether they skip when the
+// condition holds
+struct Skip {
+    when_true: Vec<u16>,
+    when_false: Vec<u16>,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, (Vec<String>, Vec<Token>)>,
+    macro_calls: usize,
+    fixups: Vec<(usize, Fixup, Token)>,
+    // jumps of open if ... begin blocks waiting for their else or end
+    branches: Vec<usize>,
+    // where each open loop starts, and the jumps out of its whiles
+    loops: Vec<(usize, Vec<usize>)>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Assembler {
+        Assembler {
+            tokens: tokenize(source),
+            rom: Vec::new(),
+            here: START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            macro_calls: 0,
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        self.tokens.pop_front().map(|(token, _)| token).ok_or_else(|| String::from("Unexpected end of program"))
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|(token, _)| token.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            ref token if token == expected => Ok(()),
+            token => Err(format!("Expected {}, found {}", expected, token))
+        }
+    }
+
+    fn write(&mut self, at: usize, bytes: &[u8]) -> Result<(), String> {
+        if at < START || at.checked_add(bytes.len()).is_none_or(|end| end > 0x10000) {
+            return Err(format!("Address {:#X} is outside the program", at));
+        }
+        let end = at - START + bytes.len();
+        if self.rom.len() < end {
+            self.rom.resize(end, 0);
+        }
+        self.rom[at - START..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn byte(&mut self, byte: u8) -> Result<(), String> {
+        let here = self.here;
+        self.write(here, &[byte])?;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, op: u16) -> Result<(), String> {
+        let here = self.here;
+        self.write(here, &[(op >> 8) as u8, op as u8])?;
+        self.here += 2;
+        Ok(())
+    }
+
+    fn patch(&mut self, at: usize, fixup: Fixup, value: usize) -> Result<(), String> {
+        match fixup {
+            Fixup::Address => {
+                if value > 0xFFF {
+                    return Err(format!("Address {:#X} doesn't fit in 12 bits", value));
+                }
+                let high = self.rom.get(at - START).cloned().unwrap_or(0) & 0xF0;
+                self.write(at, &[high | (value >> 8) as u8, value as u8])
+            },
+            Fixup::Long => self.write(at, &[(value >> 8) as u8, value as u8]),
+            Fixup::High(nibble) => self.write(at, &[nibble << 4 | (value >> 8) as u8 & 0xF]),
+            Fixup::HighByte => self.write(at, &[(value >> 8) as u8]),
+            Fixup::Low => self.write(at, &[value as u8]),
+        }
+    }
+
+    // The label's address now, or once it's defined
+    fn reference(&mut self, at: usize, fixup: Fixup, name: Token) -> Result<(), String> {
+        match self.labels.get(&name.0).cloned().or_else(|| self.constants.get(&name.0).map(|&value| value as usize)) {
+            Some(value) => self.patch(at, fixup, value),
+            None => {
+                self.fixups.push((at, fixup, name));
+                Ok(())
+            }
+        }
+    }
+
+    // An instruction ending in an address, like 1nnn
+    fn address_op(&mut self, op: u16) -> Result<(), String> {
+        let here = self.here;
+        self.emit(op)?;
+        let target = self.tokens.pop_front().ok_or("Expected an address")?;
+        match self.number(&target.0)? {
+            Some(value) => self.patch(here, Fixup::Address, value as usize),
+            None => self.reference(here, Fixup::Address, target)
+        }
+    }
+
+    fn jump(&mut self, at: usize, target: usize) -> Result<(), String> {
+        if target > 0xFFF {
+            return Err(format!("Address {:#X} doesn't fit in 12 bits", target));
+        }
+        self.write(at, &[0x10 | (target >> 8) as u8, target as u8])
+    }
+
+    // A number, a constant or a { calculation }, None for anything else
+    fn number(&mut self, token: &str) -> Result<Option<f64>, String> {
+        if token == "{" {
+            let expression = self.block()?;
+            return self.calculate(&expression).map(Some);
+        }
+        Ok(literal(token).or_else(|| self.constants.get(token).cloned()))
+    }
+
+    fn value(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        match self.number(&token)? {
+            Some(value) => Ok(value),
+            None => self.labels.get(&token).map(|&address| address as f64)
+                .ok_or_else(|| format!("{} is not a number or a known name", token))
+        }
+    }
+
+    fn byte_value(&mut self) -> Result<u8, String> {
+        let value = self.value()? as i64;
+        if !(-128..=255).contains(&value) {
+            return Err(format!("{} doesn't fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble_value(&mut self) -> Result<u16, String> {
+        let value = self.value()? as i64;
+        if !(0..=15).contains(&value) {
+            return Err(format!("{} doesn't fit in a nibble", value));
+        }
+        Ok(value as u16)
+    }
+
+    fn register_name(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|n| n as u8),
+            _ => None
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        self.register_name(&token).map(u16::from).ok_or_else(|| format!("Expected a register, found {}", token))
+    }
+
+    // The tokens up to the } matching a { that's already been read
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        let mut depth = 1;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.tokens.pop_front().ok_or("Missing }")?;
+            match token.0.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => ()
+            }
+            if depth == 0 {
+                return Ok(tokens);
+            }
+            tokens.push(token);
+        }
+    }
+
+    fn calculate(&self, expression: &[Token]) -> Result<f64, String> {
+        let tokens: Vec<&str> = expression.iter().map(|(token, _)| token.as_str()).collect();
+        let (value, rest) = self.expression(&tokens)?;
+        match rest.first() {
+            Some(token) => Err(format!("Unexpected {} in calculation", token)),
+            None => Ok(value)
+        }
+    }
+
+    // Every operator has the same precedence and they group from the right,
+    // as in Octo: { 2 * 3 + 1 } is 8.
+    fn expression<'a>(&self, tokens: &'a [&'a str]) -> Result<(f64, &'a [&'a str]), String> {
+        let (left, rest) = self.term(tokens)?;
+        let operator = match rest.first() {
+            Some(&")") | None => return Ok((left, rest)),
+            Some(&operator) => operator
+        };
+        let (right, rest) = self.expression(&rest[1..])?;
+        let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+        let value = match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (left as i64 & right as i64) as f64,
+            "|" => (left as i64 | right as i64) as f64,
+            "^" => (left as i64 ^ right as i64) as f64,
+            "<<" => ((left as i64) << (right as i64)) as f64,
+            ">>" => ((left as i64) >> (right as i64)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => truth(left < right),
+            ">" => truth(left > right),
+            "<=" => truth(left <= right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            _ => return Err(format!("Unknown operator {}", operator))
+        };
+        Ok((value, rest))
+    }
+
+    fn term<'a>(&self, tokens: &'a [&'a str]) -> Result<(f64, &'a [&'a str]), String> {
+        let (&token, rest) = tokens.split_first().ok_or("Calculation ends too soon")?;
+        if token == "(" {
+            let (value, rest) = self.expression(rest)?;
+            return match rest.split_first() {
+                Some((&")", rest)) => Ok((value, rest)),
+                _ => Err(String::from("Missing )"))
+            };
+        }
+
+        let unary: Option<fn(f64) -> f64> = match token {
+            "-" => Some(|x: f64| -x),
+            "~" => Some(|x: f64| !(x as i64) as f64),
+            "!" => Some(|x: f64| if x == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None
+        };
+        if let Some(unary) = unary {
+            let (value, rest) = self.term(rest)?;
+            return Ok((unary(value), rest));
+        }
+        if token == "@" {
+            let (address, rest) = self.term(rest)?;
+            let byte = (address as usize).checked_sub(START).and_then(|at| self.rom.get(at)).cloned().unwrap_or(0);
+            return Ok((f64::from(byte), rest));
+        }
+
+        let value = match token {
+            "PI" => Some(::std::f64::consts::PI),
+            "E" => Some(::std::f64::consts::E),
+            "HERE" => Some(self.here as f64),
+            _ => literal(token)
+                .or_else(|| self.constants.get(token).cloned())
+                .or_else(|| self.labels.get(token).map(|&address| address as f64))
+        };
+        value.map(|value| (value, rest)).ok_or_else(|| format!("{} is not defined yet", token))
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return Err(format!("{} is defined twice", name));
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    // The skips for a condition such as `v1 == 3`, `v2 key` or `v0 < v3`.
+    // Comparisons work out their answer in vF first.
+    fn condition(&mut self) -> Result<Skip, String> {
+        let x = self.register()?;
+        let comparison = self.next()?;
+        match comparison.as_str() {
+            "key" => return Ok(Skip { when_true: vec![0xE09E | x << 8], when_false: vec![0xE0A1 | x << 8] }),
+            "-key" => return Ok(Skip { when_true: vec![0xE0A1 | x << 8], when_false: vec![0xE09E | x << 8] }),
+            _ => ()
+        }
+
+        let token = self.next()?;
+        let rhs = match self.register_name(&token) {
+            Some(y) => Err(u16::from(y)),
+            None => {
+                self.tokens.push_front((token, 0));
+                Ok(u16::from(self.byte_value()?))
+            }
+        };
+
+        let (equal, not_equal) = match rhs {
+            Ok(n) => (0x3000 | x << 8 | n, 0x4000 | x << 8 | n),
+            Err(y) => (0x5000 | x << 8 | y << 4, 0x9000 | x << 8 | y << 4),
+        };
+        let (setup, holds_when) = match comparison.as_str() {
+            "==" => return Ok(Skip { when_true: vec![equal], when_false: vec![not_equal] }),
+            "!=" => return Ok(Skip { when_true: vec![not_equal], when_false: vec![equal] }),
+            // vF = no borrow from x - rhs, so x >= rhs
+            "<" | ">=" => (match rhs {
+                Ok(n) => vec![0x6F00 | n, 0x8F07 | x << 4],
+                Err(y) => vec![0x8F00 | x << 4, 0x8F05 | y << 4],
+            }, if comparison == "<" { 0 } else { 1 }),
+            // vF = no borrow from rhs - x, so x <= rhs
+            ">" | "<=" => (match rhs {
+                Ok(n) => vec![0x6F00 | n, 0x8F05 | x << 4],
+                Err(y) => vec![0x8F00 | y << 4, 0x8F05 | x << 4],
+            }, if comparison == ">" { 0 } else { 1 }),
+            _ => return Err(format!("Unknown comparison {}", comparison))
+        };
+        let mut when_true = setup.clone();
+        when_true.push(0x3F00 | holds_when);
+        let mut when_false = setup;
+        when_false.push(0x3F00 | (1 - holds_when));
+        Ok(Skip { when_true, when_false })
+    }
+
+    fn emit_all(&mut self, ops: &[u16]) -> Result<(), String> {
+        for &op in ops {
+            self.emit(op)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.tokens.pop_front().ok_or("Unexpected end of program")?;
+        match token.0.as_str() {
+            ":" => {
+                let name = self.next()?;
+                let here = self.here;
+                self.define_label(name, here)?;
+            },
+            ":next" => {
+                let name = self.next()?;
+                let here = self.here;
+                self.define_label(name, here + 1)?;
+            },
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            },
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let expression = self.block()?;
+                let value = self.calculate(&expression)?;
+                self.constants.insert(name, value);
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let token = self.next()?;
+                let register = match self.register_name(&token) {
+                    Some(register) => register,
+                    None => match self.number(&token)? {
+                        Some(value) if (0.0..16.0).contains(&value) => value as u8,
+                        _ => return Err(format!("{} is not a register", token))
+                    }
+                };
+                self.aliases.insert(name, register);
+            },
+            ":unpack" => {
+                let high = match self.peek() {
+                    Some("long") => {
+                        self.next()?;
+                        Fixup::HighByte
+                    },
+                    _ => Fixup::High(self.nibble_value()? as u8)
+                };
+                let name = self.tokens.pop_front().ok_or("Expected a label")?;
+                let here = self.here;
+                self.emit(0x6000)?;
+                self.emit(0x6100)?;
+                self.reference(here + 1, high, name.clone())?;
+                self.reference(here + 3, Fixup::Low, name)?;
+            },
+            ":org" => self.here = self.value()? as usize,
+            ":byte" => {
+                let byte = self.byte_value()?;
+                self.byte(byte)?;
+            },
+            ":pointer" => {
+                let here = self.here;
+                self.byte(0)?;
+                self.byte(0)?;
+                let target = self.tokens.pop_front().ok_or("Expected an address")?;
+                match self.number(&target.0)? {
+                    Some(value) => self.patch(here, Fixup::Long, value as usize)?,
+                    None => self.reference(here, Fixup::Long, target)?
+                }
+            },
+            ":call" => self.address_op(0x2000)?,
+            ":macro" => {
+                let name = self.next()?;
+                let mut parameters = Vec::new();
+                while self.peek() != Some("{") {
+                    parameters.push(self.next()?);
+                }
+                self.next()?;
+                let body = self.block()?;
+                self.macros.insert(name, (parameters, body));
+            },
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(token) if token.starts_with('"') => Some(self.next()?),
+                    _ => None
+                };
+                if self.value()? == 0.0 {
+                    return Err(message.map_or_else(|| String::from("Assertion failed"),
+                                                   |message| message.trim_matches('"').to_string()));
+                }
+            },
+            ":breakpoint" | ":proto" => {
+                self.next()?;
+            },
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            },
+            "return" | ";" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "hires" => self.emit(0x00FF)?,
+            "lores" => self.emit(0x00FE)?,
+            "exit" => self.emit(0x00FD)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-down" => {
+                let n = self.nibble_value()?;
+                self.emit(0x00C0 | n)?;
+            },
+            "scroll-up" => {
+                let n = self.nibble_value()?;
+                self.emit(0x00D0 | n)?;
+            },
+            "audio" => self.emit(0xF002)?,
+            "plane" => {
+                let n = self.nibble_value()?;
+                self.emit(0xF001 | n << 8)?;
+            },
+            "jump" => self.address_op(0x1000)?,
+            "jump0" => self.address_op(0xB000)?,
+            "native" => self.address_op(0x0000)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | x << 8)?;
+            },
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | x << 8)?;
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | x << 8)?;
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                let store = token.0 == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.emit(if store { 0x5002 } else { 0x5003 } | x << 8 | y << 4)?;
+                } else {
+                    self.emit(if store { 0xF055 } else { 0xF065 } | x << 8)?;
+                }
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble_value()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let op = match token.0.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A
+                };
+                self.emit(op | x << 8)?;
+            },
+            "i" => self.index()?,
+            "if" => {
+                let skip = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.emit_all(&skip.when_false)?,
+                    "begin" => {
+                        self.emit_all(&skip.when_true)?;
+                        self.branches.push(self.here);
+                        self.emit(0x1000)?;
+                    },
+                    other => return Err(format!("Expected then or begin, found {}", other))
+                }
+            },
+            "else" => {
+                let open = self.branches.pop().ok_or("else without if ... begin")?;
+                self.branches.push(self.here);
+                self.emit(0x1000)?;
+                let here = self.here;
+                self.jump(open, here)?;
+            },
+            "end" => {
+                let open = self.branches.pop().ok_or("end without if ... begin")?;
+                let here = self.here;
+                self.jump(open, here)?;
+            },
+            "loop" => self.loops.push((self.here, Vec::new())),
+            "while" => {
+                let skip = self.condition()?;
+                self.emit_all(&skip.when_true)?;
+                let here = self.here;
+                self.loops.last_mut().ok_or("while outside a loop")?.1.push(here);
+                self.emit(0x1000)?;
+            },
+            "again" => {
+                let (start, exits) = self.loops.pop().ok_or("again without loop")?;
+                let here = self.here;
+                self.emit(0x1000)?;
+                self.jump(here, start)?;
+                for exit in exits {
+                    let here = self.here;
+                    self.jump(exit, here)?;
+                }
+            },
+            name if self.macros.contains_key(name) => {
+                let (parameters, body) = self.macros[name].clone();
+                let mut arguments = HashMap::new();
+                for parameter in parameters {
+                    arguments.insert(parameter, self.next()?);
+                }
+                self.macro_calls += 1;
+                if self.macro_calls > MAX_MACRO_CALLS {
+                    return Err(format!("More than {} macro expansions, does {} call itself?", MAX_MACRO_CALLS, name));
+                }
+                for (token, line) in body.into_iter().rev() {
+                    let token = match arguments.get(&token) {
+                        Some(argument) => argument.clone(),
+                        None if token == "CALLS" => self.macro_calls.to_string(),
+                        None => token
+                    };
+                    self.tokens.push_front((token, line));
+                }
+            },
+            name if self.register_name(name).is_some() => {
+                let x = u16::from(self.register_name(name).unwrap_or(0));
+                self.register_op(x)?;
+            },
+            name if name.starts_with(':') => return Err(format!("{} is not supported", name)),
+            _ => match self.number(&token.0)? {
+                // bare numbers are data
+                Some(value) => {
+                    self.tokens.push_front((value.to_string(), token.1));
+                    let byte = self.byte_value()?;
+                    self.byte(byte)?;
+                },
+                // and bare names are subroutine calls
+                None => {
+                    let here = self.here;
+                    self.emit(0x2000)?;
+                    self.reference(here, Fixup::Address, token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        if operator == "+=" {
+            let x = self.register()?;
+            return self.emit(0xF01E | x << 8);
+        }
+        if operator != ":=" {
+            return Err(format!("Expected := or += after i, found {}", operator));
+        }
+        match self.peek() {
+            Some("hex") | Some("bighex") => {
+                let op = if self.next()? == "hex" { 0xF029 } else { 0xF030 };
+                let x = self.register()?;
+                self.emit(op | x << 8)
+            },
+            Some("long") => {
+                self.next()?;
+                self.emit(0xF000)?;
+                let here = self.here;
+                self.emit(0)?;
+                let target = self.tokens.pop_front().ok_or("Expected an address")?;
+                match self.number(&target.0)? {
+                    Some(value) => self.patch(here, Fixup::Long, value as usize),
+                    None => self.reference(here, Fixup::Long, target)
+                }
+            },
+            _ => self.address_op(0xA000)
+        }
+    }
+
+    fn register_op(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let token = self.next()?;
+        if let Some(y) = self.register_name(&token).map(u16::from) {
+            let low = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(format!("Unknown operator {}", operator))
+            };
+            return self.emit(0x8000 | x << 8 | y << 4 | low);
+        }
+
+        match (operator.as_str(), token.as_str()) {
+            (":=", "key") => self.emit(0xF00A | x << 8),
+            (":=", "delay") => self.emit(0xF007 | x << 8),
+            (":=", "random") => {
+                let mask = self.byte_value()?;
+                self.emit(0xC000 | x << 8 | u16::from(mask))
+            },
+            (":=", _) | ("+=", _) | ("-=", _) => {
+                self.tokens.push_front((token, 0));
+                let n = self.byte_value()?;
+                match operator.as_str() {
+                    ":=" => self.emit(0x6000 | x << 8 | u16::from(n)),
+                    "+=" => self.emit(0x7000 | x << 8 | u16::from(n)),
+                    _ => self.emit(0x7000 | x << 8 | u16::from(n.wrapping_neg()))
+                }
+            },
+            _ => Err(format!("Can't use {} with {}", operator, token))
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        if !self.branches.is_empty() {
+            return Err(String::from("if ... begin without end"));
+        }
+        if !self.loops.is_empty() {
+            return Err(String::from("loop without again"));
+        }
+        for (at, fixup, (name, line)) in ::std::mem::take(&mut self.fixups) {
+            let value = match self.labels.get(&name) {
+                Some(&address) => address,
+                None => return Err(format!("Line {}: {} is never defined", line, name))
+            };
+            self.patch(at, fixup, value)?;
+        }
+        let main = *self.labels.get("main").ok_or("The program has no main label")?;
+        self.jump(START, main)?;
+        Ok(self.rom)
+    }
+}
+
+// Words split on white space, # comments dropped and "quoted strings" kept whole
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (number, line) in source.lines().enumerate() {
+        let mut rest = line.trim_start();
+        while !rest.is_empty() && !rest.starts_with('#') {
+            let end = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.find('"').map_or(rest.len(), |end| end + 2),
+                None => rest.find(char::is_whitespace).unwrap_or(rest.len())
+            };
+            tokens.push_back((rest[..end].to_string(), number + 1));
+            rest = rest[end..].trim_start();
+        }
+    }
+    tokens
+}
+
+// 42, -3, 0x2A or 0b101010
+fn literal(token: &str) -> Option<f64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token)
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use gamedb::GameDb;
+    use octo::assemble;
+
+    #[test]
+    fn test_main_and_instructions() {
+        let rom = assemble(": main\n  clear\n  v0 := 5 v1 += v0\n  i := sprite sprite v0 v1 4\n  loop again\n\
+                            : sprite 0xF0 0x90 0x90 0xF0").unwrap();
+        assert_eq!(rom, vec![
+            0x12, 0x02, // jump main
+            0x00, 0xE0, 0x60, 0x05, 0x81, 0x04,
+            0xA2, 0x0E, 0xD0, 0x14,
+            0x12, 0x0C, // loop again
+            0xF0, 0x90, 0x90, 0xF0,
+        ]);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let rom = assemble(": main\nif v1 == 3 then v2 := 1\nif v3 key begin v4 := 2 else v4 := 3 end\n\
+                            loop while v5 != 0 v5 -= 1 again").unwrap();
+        assert_eq!(rom, vec![
+            0x12, 0x02,
+            0x41, 0x03, 0x62, 0x01, // skip unless v1 == 3
+            0xE3, 0x9E, 0x12, 0x0E, 0x64, 0x02, 0x12, 0x10, 0x64, 0x03, // if key begin else end
+            0x45, 0x00, 0x12, 0x18, 0x75, 0xFF, 0x12, 0x10, // loop while again
+        ]);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let source = ": main\nv0 := 7\nv5 := 7\n\
+                      if v0 < 5 then v1 := 1\nif v0 > 5 then v2 := 1\nif v0 <= 6 then v3 := 1\n\
+                      if v0 >= v5 then v4 := 1\nif v0 > v5 then v6 := 1\n: end jump end";
+        let mut cpu = Cpu::new();
+        cpu.set_game_db(GameDb::parse("").unwrap());
+        cpu.load_rom(assemble(source).unwrap());
+        for _ in 0..30 {
+            cpu.step([false; 16]);
+        }
+        // only the bodies of v0 > 5 and v0 >= v5 ran
+        assert_eq!(cpu.v()[1..7], [0, 1, 0, 1, 7, 0]);
+    }
+
+    #[test]
+    fn test_directives() {
+        let source = ":alias x v3\n:const SPEED 2\n:calc DOUBLE { SPEED * 2 + 1 }\n\
+                      :macro nudge reg { reg += SPEED }\n\
+                      : main\nx := DOUBLE\nnudge x\ni := long data\n:unpack 0xA data\n: data :byte { 7 - 2 }";
+        assert_eq!(assemble(source).unwrap(), vec![
+            0x12, 0x02,
+            0x63, 0x06, // 2 * (2 + 1)
+            0x73, 0x02,
+            0xF0, 0x00, 0x02, 0x0E,
+            0x60, 0xA2, 0x61, 0x0E,
+            0x05,
+        ]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(assemble("clear").unwrap_err().contains("main"));
+        assert_eq!(assemble(": main jump nowhere").unwrap_err(), "Line 1: nowhere is never defined");
+        assert_eq!(assemble(": main\nv0 := 300").unwrap_err(), "Line 2: 300 doesn't fit in a byte");
+        assert!(assemble(":macro forever { forever }\n: main forever").unwrap_err().contains("macro expansions"));
+        assert!(assemble(":org 0x1000 : main").unwrap_err().contains("12 bits"));
+        assert!(assemble(": main :org 0xFFFF 1 2").unwrap_err().contains("outside the program"));
+    }
+
+    #[test]
+    fn test_unpack_long() {
+        let rom = assemble(": main :unpack long data :unpack 0xA data\n:org 0x1234 : data 0x01").unwrap();
+        assert_eq!(rom[2..10], [0x60, 0x12, 0x61, 0x34, 0x60, 0xA2, 0x61, 0x34]);
+        assert_eq!(rom.len(), 0x1235 - 0x200);
+    }
+}
@@ -0,0 +1,157 @@
+use toml::Value;
+
+pub type Rgb = (u8, u8, u8);
+
+// Enough for four bit planes. Index 0 is the background, 1 is the first
+// plane, 2 the second, 3 both and so on.
+pub const PALETTE_SIZE: usize = 16;
+
+// The VP-590 color board's eight colors as CHIP-8X numbers them: bit 0 is
+// red, bit 1 blue and bit 2 green
+pub const CHIP8X_COLORS: [Rgb; 8] = [
+    (0x00, 0x00, 0x00),
+    (0xFF, 0x00, 0x00),
+    (0x00, 0x00, 0xFF),
+    (0xFF, 0x00, 0xFF),
+    (0x00, 0xFF, 0x00),
+    (0xFF, 0xFF, 0x00),
+    (0x00, 0xFF, 0xFF),
+    (0xFF, 0xFF, 0xFF),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    colors: Vec<Rgb>,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::presets().remove(0)
+    }
+}
+
+impl Palette {
+    // Colors past the ones given repeat the last one, so a two color palette
+    // still shows every plane.
+    pub fn new(name: &str, colors: &[Rgb]) -> Palette {
+        let mut colors: Vec<Rgb> = colors.iter().take(PALETTE_SIZE).cloned().collect();
+        let last = *colors.last().unwrap_or(&(0, 0, 0));
+        colors.resize(PALETTE_SIZE, last);
+        Palette { name: String::from(name), colors }
+    }
+
+    pub fn presets() -> Vec<Palette> {
+        vec![
+            // the green on black this emulator has always used
+            Palette::new("classic", &[(0x00, 0x00, 0x00), (0x00, 0xFA, 0x00), (0x00, 0x7D, 0x00), (0xAA, 0xFF, 0xAA)]),
+            Palette::new("amber", &[(0x1A, 0x10, 0x00), (0xFF, 0xB0, 0x00), (0x99, 0x60, 0x00), (0xFF, 0xE0, 0x80)]),
+            Palette::new("lcd", &[(0x9B, 0xBC, 0x0F), (0x0F, 0x38, 0x0F), (0x30, 0x62, 0x30), (0x8B, 0xAC, 0x0F)]),
+            Palette::new("hp48", &[(0xC7, 0xCE, 0xB8), (0x2A, 0x2F, 0x2A), (0x7B, 0x80, 0x78), (0x50, 0x55, 0x50)]),
+        ]
+    }
+
+    // "amber", or a comma separated list of hex colors, background first:
+    // "000000,ffb000" or "#222,#eee"
+    pub fn parse(spec: &str) -> Result<Palette, String> {
+        if let Some(preset) = Palette::presets().into_iter().find(|preset| preset.name == spec) {
+            return Ok(preset);
+        }
+
+        let colors = spec.split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Result<Vec<Rgb>, String>>()?;
+        if colors.len() < 2 {
+            return Err(format!("{} is not a palette name or a list of at least two colors", spec));
+        }
+        Ok(Palette::new("custom", &colors))
+    }
+
+    // palette = "amber" or palette = ["#000000", "#ffb000"] under [display]
+    pub fn from_config(config: &Value) -> Result<Option<Palette>, String> {
+        match config.get("display").and_then(|display| display.get("palette")) {
+            Some(palette) => Palette::from_value(palette).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    // "amber" or ["#000000", "#ffb000"]
+    pub fn from_value(palette: &Value) -> Result<Palette, String> {
+        match *palette {
+            Value::String(ref spec) => Palette::parse(spec),
+            Value::Array(ref colors) => {
+                let colors = colors.iter()
+                    .map(|color| color.as_str().ok_or("Palette colors must be strings"))
+                    .collect::<Result<Vec<&str>, &str>>()?;
+                Palette::parse(&colors.join(","))
+            },
+            _ => Err(String::from("palette must be a name or a list of colors"))
+        }
+    }
+
+    pub fn colors(&self) -> &[Rgb] {
+        &self.colors
+    }
+
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors[(pixel as usize).min(PALETTE_SIZE - 1)]
+    }
+}
+
+// "#rrggbb", "rrggbb" or the short "#rgb"
+fn parse_color(color: &str) -> Result<Rgb, String> {
+    let hex = color.trim_start_matches('#');
+    if !hex.is_ascii() {
+        return Err(format!("{} is not a hex color", color));
+    }
+    let digits: Vec<u8> = match hex.len() {
+        6 => (0..3).map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)).collect::<Result<_, _>>(),
+        3 => (0..3).map(|i| u8::from_str_radix(&hex[i..i + 1], 16).map(|d| d * 17)).collect::<Result<_, _>>(),
+        _ => return Err(format!("{} is not a hex color", color))
+    }.map_err(|_| format!("{} is not a hex color", color))?;
+
+    Ok((digits[0], digits[1], digits[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::{Palette, PALETTE_SIZE};
+
+    #[test]
+    fn test_default_is_classic() {
+        let palette = Palette::default();
+        assert_eq!(palette.name, "classic");
+        assert_eq!(palette.color(0), (0, 0, 0));
+        assert_eq!(palette.color(1), (0, 250, 0));
+    }
+
+    #[test]
+    fn test_parse_preset() {
+        assert_eq!(Palette::parse("amber").unwrap().color(1), (0xFF, 0xB0, 0x00));
+    }
+
+    #[test]
+    fn test_parse_custom() {
+        let palette = Palette::parse("#101010, ffffff").unwrap();
+        assert_eq!(palette.colors().len(), PALETTE_SIZE);
+        assert_eq!(palette.color(0), (0x10, 0x10, 0x10));
+        // planes without a color of their own use the last one
+        assert_eq!(palette.color(3), (0xFF, 0xFF, 0xFF));
+        assert_eq!(Palette::parse("#f80,#000").unwrap().color(0), (0xFF, 0x88, 0x00));
+    }
+
+    #[test]
+    fn test_parse_bad() {
+        assert!(Palette::parse("sepia").is_err());
+        assert!(Palette::parse("#000000").is_err());
+        assert!(Palette::parse("#000000,#gg0000").is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = "[display]\npalette = [\"#000\", \"#fff\"]".parse().unwrap();
+        assert_eq!(Palette::from_config(&config).unwrap().unwrap().color(1), (255, 255, 255));
+        let config = "[keys]\n1 = \"A\"".parse().unwrap();
+        assert_eq!(Palette::from_config(&config), Ok(None));
+    }
+}
@@ -0,0 +1,220 @@
+use CHIP8_HEIGHT;
+use CHIP8_WIDTH;
+
+// The machines CHIP-8 programs were written for. They disagree on a handful
+// of instructions, so a game only plays right with the behaviour it expects.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Platform {
+    // the original interpreter on the COSMAC VIP
+    Chip8,
+    // SUPER-CHIP on the HP 48 calculators
+    Schip,
+    // Octo's XO-CHIP
+    XoChip,
+    // the ETI-660 kit computer, with a taller screen and programs at 0x600
+    Eti660,
+    // the DREAM 6800 and its CHIPOS monitor
+    Dream6800,
+    // CHIP-8X, for the VIP with the VP-590 color board
+    Chip8X,
+    // MegaChip8, SUPER-CHIP with a 256x192 color screen and sampled sound
+    MegaChip,
+}
+
+impl Platform {
+    pub fn parse(name: &str) -> Result<Platform, String> {
+        match name {
+            "chip8" | "chip-8" | "vip" => Ok(Platform::Chip8),
+            "schip" | "superchip" => Ok(Platform::Schip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            "eti660" | "eti-660" => Ok(Platform::Eti660),
+            "dream6800" | "dream" => Ok(Platform::Dream6800),
+            "chip8x" | "chip-8x" => Ok(Platform::Chip8X),
+            "megachip" | "megachip8" => Ok(Platform::MegaChip),
+            _ => Err(format!("Unknown platform {}, try chip8, schip, xochip, eti660, dream6800, chip8x or megachip", name))
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Schip => "schip",
+            Platform::XoChip => "xochip",
+            Platform::Eti660 => "eti660",
+            Platform::Dream6800 => "dream6800",
+            Platform::Chip8X => "chip8x",
+            Platform::MegaChip => "megachip",
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks { shift_vy: true, increment_i: true, jump_vx: false, vf_reset: true, clip: true },
+            Platform::Schip | Platform::MegaChip => Quirks { shift_vy: false, increment_i: false, jump_vx: true, vf_reset: false, clip: true },
+            Platform::XoChip => Quirks { shift_vy: true, increment_i: true, jump_vx: false, vf_reset: false, clip: false },
+            // all three interpreters were written after the VIP's
+            Platform::Eti660 | Platform::Dream6800 | Platform::Chip8X => Platform::Chip8.quirks(),
+        }
+    }
+
+    pub fn profile(self) -> Profile {
+        match self {
+            Platform::Eti660 => Profile { load_address: 0x600, height: 48, font: &ETI660_FONT, ..Profile::default() },
+            // the 2K of RAM the expanded board had, CHIPOS's own memory sits below 0x200
+            Platform::Dream6800 => Profile { memory_size: 2048, font: &DREAM6800_FONT, ..Profile::default() },
+            // the bigger interpreter takes up the first 768 bytes
+            Platform::Chip8X => Profile { load_address: 0x300, ..Profile::default() },
+            // 0011 switches to the big screen, it starts as CHIP-8's
+            Platform::MegaChip => Profile { memory_size: MEGACHIP_MEMORY, ..Profile::default() },
+            _ => Profile::default(),
+        }
+    }
+}
+
+// MegaChip8's 32 MB, though the 24 bit addresses of 01nn nnnn only reach the first 16
+const MEGACHIP_MEMORY: usize = 32 << 20;
+
+// The hex digits Fx29 points at, 4 pixels wide and 5 high
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,
+    0x20, 0x60, 0x20, 0x20, 0x70,
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    0x90, 0x90, 0xF0, 0x10, 0x10,
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,
+    0xF0, 0x10, 0x20, 0x40, 0x40,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,
+    0xF0, 0x90, 0xF0, 0x90, 0x90,
+    0xE0, 0x90, 0xE0, 0x90, 0xE0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0,
+    0xE0, 0x90, 0x90, 0x90, 0xE0,
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0x80,
+];
+
+// SUPER-CHIP's big digits Fx30 points at, 8 pixels wide and 10 high.
+// SUPER-CHIP 1.1 only had 0 to 9, the letters are Octo's.
+pub const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+];
+
+// The ETI-660's and CHIPOS's own digits, 3 pixels wide
+const ETI660_FONT: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0,
+    0x20, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0x20, 0xE0, 0x80, 0xE0,
+    0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0xA0, 0xA0, 0xE0, 0x20, 0x20,
+    0xE0, 0x80, 0xE0, 0x20, 0xE0,
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0,
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0,
+    0x80, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0,
+    0x20, 0x20, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x80, 0xE0, 0x80, 0xE0,
+    0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+const DREAM6800_FONT: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0,
+    0x40, 0x40, 0x40, 0x40, 0x40,
+    0xE0, 0x20, 0xE0, 0x80, 0xE0,
+    0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0x80, 0xA0, 0xA0, 0xE0, 0x20,
+    0xE0, 0x80, 0xE0, 0x20, 0xE0,
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0,
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0,
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0,
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0,
+    0xE0, 0x80, 0xE0, 0x80, 0xE0,
+    0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+// What a platform's programs find when they start: where they were loaded,
+// how much memory there is, how big the screen is and what the digits look like
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub load_address: u16,
+    pub memory_size: usize,
+    pub width: usize,
+    pub height: usize,
+    pub font: &'static [u8; 80],
+}
+
+// The COSMAC VIP's, which most platforms kept
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile { load_address: 0x200, memory_size: 4096, width: CHIP8_WIDTH, height: CHIP8_HEIGHT, font: &FONT }
+    }
+}
+
+// The instructions that behave differently between platforms. The default
+// is what this emulator has always done, which matches none of them exactly.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift V[y] into V[x] rather than shifting V[x] in place
+    pub shift_vy: bool,
+    // Fx55/Fx65 leave I pointing past the last register they touched
+    pub increment_i: bool,
+    // Bnnn jumps to nnn + V[x], x being the top digit of nnn, instead of V[0]
+    pub jump_vx: bool,
+    // 8xy1/8xy2/8xy3 clear VF
+    pub vf_reset: bool,
+    // sprites are cut off at the edge of the screen instead of wrapping round
+    pub clip: bool,
+}
+
+impl Quirks {
+    // A platform name, or "legacy" for the defaults
+    pub fn preset(name: &str) -> Result<Quirks, String> {
+        match name {
+            "legacy" => Ok(Quirks::default()),
+            _ => Platform::parse(name).map(Platform::quirks)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use platform::{Platform, Profile, Quirks};
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Quirks::preset("legacy"), Ok(Quirks::default()));
+        assert_eq!(Quirks::preset("vip"), Ok(Platform::Chip8.quirks()));
+        assert!(Quirks::preset("schip").unwrap().jump_vx);
+        assert!(Quirks::preset("chip48").is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let eti = Platform::parse("eti-660").unwrap().profile();
+        assert_eq!((eti.load_address, eti.width, eti.height), (0x600, 64, 48));
+        assert_eq!(Platform::Schip.profile(), Profile::default());
+        assert_ne!(Platform::Dream6800.profile().font[..], Profile::default().font[..]);
+    }
+}
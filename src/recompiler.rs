@@ -0,0 +1,301 @@
+use std::collections::BTreeSet;
+
+use instruction::convert_op;
+use instruction::Instruction::*;
+use platform::Quirks;
+
+// Where ROMs are loaded
+const START: usize = 0x200;
+
+// A run of instructions that's only ever entered at the top
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    // just past the last instruction
+    pub end: usize,
+}
+
+// The code found by following the program from its entry point: every
+// jump, call and skip target starts a block, and so does whatever follows an
+// instruction that writes memory, so a block never runs on after changing
+// itself. Computed jumps (Bnnn) can't be followed, where they land is left
+// to the interpreter.
+pub fn find_blocks(rom: &[u8]) -> Vec<Block> {
+    let mut code = BTreeSet::new();
+    let mut leaders = BTreeSet::new();
+    let mut pending = vec![START];
+    leaders.insert(START);
+    while let Some(pc) = pending.pop() {
+        let op = match word(rom, pc) {
+            Some(op) if !code.contains(&pc) => op,
+            _ => continue
+        };
+        code.insert(pc);
+        let (ends_block, next) = successors(rom, pc);
+        if ends_block {
+            leaders.extend(next.iter().cloned());
+        }
+        if let WaitKey {..} = convert_op(op) {
+            // it may run again and again, so it goes at the top of a block
+            leaders.insert(pc);
+        }
+        pending.extend(next);
+    }
+
+    leaders.iter().filter(|leader| code.contains(leader)).map(|&start| {
+        let mut pc = start;
+        loop {
+            let ends_block = successors(rom, pc).0;
+            pc += length(rom, pc);
+            if ends_block || !code.contains(&pc) || leaders.contains(&pc) {
+                return Block { start, end: pc };
+            }
+        }
+    }).collect()
+}
+
+// Whether the instruction at pc ends its block, and where it can go next
+fn successors(rom: &[u8], pc: usize) -> (bool, Vec<usize>) {
+    let (next, skipped) = (pc + length(rom, pc), pc + 2 + length(rom, pc + 2));
+    let op = match word(rom, pc) {
+        Some(op) => op,
+        None => return (true, vec![])
+    };
+    match convert_op(op) {
+        Jmp {location} => (true, vec![location as usize]),
+        Call {location} => (true, vec![location as usize, next]),
+        Ret | JmpA {..} => (true, vec![]),
+        ConstantSe {..} | ConstantSne {..} | Se {..} | Sne {..} | Skp {..} | Sknp {..} => (true, vec![next, skipped]),
+        SetLong {..} | LdB {..} | WaitKey {..} => (true, vec![next]),
+        _ => (false, vec![next])
+    }
+}
+
+// Rust for one instruction at pc. Ones that end their block are an
+// expression for where to go next, the rest are statements.
+fn translate(rom: &[u8], pc: usize, quirks: Quirks) -> String {
+    let op = rom_word(rom, pc);
+    let reset_vf = if quirks.vf_reset { " v[0xF] = 0;" } else { "" };
+    let shifted = |x: u8, y: u8| if quirks.shift_vy { y } else { x };
+    // skips hop over both words of F000 nnnn
+    let skipped = pc + 2 + length(rom, pc + 2);
+    let skip = |condition: String| format!("if {} {{ {:#06X} }} else {{ {:#06X} }}", condition, skipped, pc + 2);
+
+    match convert_op(op) {
+        Set {reg, byte} => format!("c.v()[{}] = {:#04X};", reg, byte),
+        AddO {reg, byte} => format!("{{ let v = c.v(); v[{0}] = v[{0}].wrapping_add({1:#04X}); }}", reg, byte),
+        Load {regx, regy} => format!("{{ let v = c.v(); v[{}] = v[{}]; }}", regx, regy),
+        Or {regx, regy} => format!("{{ let v = c.v(); v[{}] |= v[{}];{} }}", regx, regy, reset_vf),
+        And {regx, regy} => format!("{{ let v = c.v(); v[{}] &= v[{}];{} }}", regx, regy, reset_vf),
+        Xor {regx, regy} => format!("{{ let v = c.v(); v[{}] ^= v[{}];{} }}", regx, regy, reset_vf),
+        Add {regx, regy} => format!("{{ let v = c.v(); let (sum, carry) = v[{0}].overflowing_add(v[{1}]); \
+                                     v[{0}] = sum; v[0xF] = carry as u8; }}", regx, regy),
+        Sub {regx, regy} => format!("{{ let v = c.v(); let (x, y) = (v[{0}], v[{1}]); \
+                                     v[{0}] = x.wrapping_sub(y); v[0xF] = (x >= y) as u8; }}", regx, regy),
+        Subn {regx, regy} => format!("{{ let v = c.v(); let (x, y) = (v[{0}], v[{1}]); \
+                                      v[{0}] = y.wrapping_sub(x); v[0xF] = (y >= x) as u8; }}", regx, regy),
+        Shr {regx, regy} => format!("{{ let v = c.v(); let x = v[{}]; v[{}] = x >> 1; v[0xF] = x & 1; }}",
+                                    shifted(regx, regy), regx),
+        Shl {regx, regy} => format!("{{ let v = c.v(); let x = v[{}]; v[{}] = x << 1; v[0xF] = x >> 7; }}",
+                                    shifted(regx, regy), regx),
+        LdI {loc} => format!("*c.index() = {:#05X};", loc),
+        LdILong => format!("*c.index() = {:#06X};", word(rom, pc + 2).unwrap_or(0)),
+        AddI {reg} => format!("{{ let x = c.v()[{}] as u32; let i = c.index(); *i = i.wrapping_add(x); }}", reg),
+
+        Jmp {location} => format!("{:#06X}", location),
+        Call {location} => format!("{{ c.call({:#06X}); {:#06X} }}", pc + 2, location),
+        Ret => String::from("c.ret()"),
+        JmpA {loc} => {
+            let reg = if quirks.jump_vx { (loc >> 8) & 0xF } else { 0 };
+            format!("c.v()[{}] as u16 + {:#05X}", reg, loc)
+        },
+        ConstantSe {reg, byte} => skip(format!("c.v()[{}] == {:#04X}", reg, byte)),
+        ConstantSne {reg, byte} => skip(format!("c.v()[{}] != {:#04X}", reg, byte)),
+        Se {x, y} => skip(format!("{{ let v = c.v(); v[{}] == v[{}] }}", x, y)),
+        Sne {regx, regy} => skip(format!("{{ let v = c.v(); v[{}] != v[{}] }}", regx, regy)),
+        Skp {key} => skip(format!("c.key({})", key)),
+        Sknp {key} => skip(format!("!c.key({})", key)),
+        SetLong {..} | LdB {..} => format!("{{ c.exec({:#06X}); {:#06X} }}", op, pc + 2),
+        WaitKey {reg} => format!("match c.pressed_key() {{\n        Some(key) => {{ c.v()[{}] = key; {:#06X} }}\n        \
+                                  None => {:#06X}\n    }}", reg, pc + 2, pc),
+
+        // drawing, timers and the rest go through the interpreter
+        _ => format!("c.exec({:#06X});", op)
+    }
+}
+
+// A Rust program that runs the ROM through the chip8_cpu runtime, with one
+// function per block and a dispatcher that sends anything else, including
+// blocks the program has since written over, to the interpreter.
+pub fn recompile(rom: &[u8], quirks: Quirks) -> String {
+    let blocks = find_blocks(rom);
+    let mut out = String::new();
+
+    out.push_str("// Recompiled from a CHIP-8 ROM by chip8-recompile.\n\n");
+    // blocks that only jump somewhere don't touch the machine
+    out.push_str("#![allow(unused_variables)]\n\n");
+    out.push_str("extern crate chip8_cpu;\n\n");
+    out.push_str("use chip8_cpu::cpu::Cpu;\nuse chip8_cpu::cpu::runtime;\nuse chip8_cpu::platform::Quirks;\n\n");
+
+    out.push_str(&format!("const ROM: [u8; {}] = [", rom.len()));
+    for (n, byte) in rom.iter().enumerate() {
+        out.push_str(if n % 16 == 0 { "\n    " } else { " " });
+        out.push_str(&format!("{:#04X},", byte));
+    }
+    out.push_str("\n];\n\n");
+    out.push_str(&format!("const QUIRKS: Quirks = Quirks {{ shift_vy: {}, increment_i: {}, jump_vx: {}, vf_reset: {}, clip: {} }};\n",
+                          quirks.shift_vy, quirks.increment_i, quirks.jump_vx, quirks.vf_reset, quirks.clip));
+
+    for block in &blocks {
+        out.push_str(&format!("\nfn block_{:04x}(c: &mut Cpu, budget: &mut u32) -> u16 {{\n", block.start));
+        let (mut pc, mut last) = (block.start, block.start);
+        while pc < block.end {
+            out.push_str(&format!("    // {:04X}: {:04X}\n", pc, rom_word(rom, pc)));
+            out.push_str(&format!("    if *budget == 0 {{\n        return {:#06X};\n    }}\n    *budget -= 1;\n", pc));
+            out.push_str(&format!("    {}\n", translate(rom, pc, quirks)));
+            last = pc;
+            pc += length(rom, pc);
+        }
+        if !successors(rom, last).0 {
+            out.push_str(&format!("    {:#06X}\n", block.end));
+        }
+        out.push_str("}\n");
+    }
+
+    out.push_str("\nfn run(c: &mut Cpu, mut pc: u16, budget: &mut u32) -> u16 {\n");
+    out.push_str("    while *budget > 0 {\n        pc = match pc {\n");
+    for block in &blocks {
+        out.push_str(&format!("            {:#06X} if c.memory()[{:#05X}..{:#05X}] == ROM[{:#05X}..{:#05X}] => block_{:04x}(c, budget),\n",
+                              block.start, block.start, block.end, block.start - START, block.end - START, block.start));
+    }
+    out.push_str("            _ => {\n                *budget -= 1;\n                c.interpret(pc)\n            }\n");
+    out.push_str("        };\n    }\n    pc\n}\n\n");
+    out.push_str("fn main() {\n    runtime::main(&ROM, QUIRKS, run);\n}\n");
+    out
+}
+
+fn rom_word(rom: &[u8], pc: usize) -> u16 {
+    (rom[pc - START] as u16) << 8 | rom[pc - START + 1] as u16
+}
+
+// None outside the ROM
+fn word(rom: &[u8], pc: usize) -> Option<u16> {
+    if pc >= START && pc + 1 < START + rom.len() {
+        Some(rom_word(rom, pc))
+    } else {
+        None
+    }
+}
+
+// Bytes the instruction at pc takes up, 2 past the end of the ROM
+fn length(rom: &[u8], pc: usize) -> usize {
+    word(rom, pc).map_or(2, |op| convert_op(op).length())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process::{self, Command};
+
+    use platform::Quirks;
+    use recompiler::{find_blocks, recompile, Block};
+
+    const ROM: [u8; 14] = [
+        0x60, 0x00, // 200: V0 = 0
+        0x70, 0x01, // 202: V0 += 1
+        0x30, 0x05, // 204: skip if V0 == 5
+        0x12, 0x02, // 206: jump 202
+        0xF0, 0x0A, // 208: wait for a key
+        0xB2, 0x00, // 20A: jump to 200 + V0
+        0x12, 0x00, // 20C: not reached as far as anyone can tell
+    ];
+
+    #[test]
+    fn test_find_blocks() {
+        assert_eq!(find_blocks(&ROM), vec![
+            Block { start: 0x200, end: 0x202 },
+            Block { start: 0x202, end: 0x206 },
+            Block { start: 0x206, end: 0x208 },
+            Block { start: 0x208, end: 0x20A },
+            Block { start: 0x20A, end: 0x20C },
+        ]);
+    }
+
+    #[test]
+    fn test_recompile() {
+        let source = recompile(&ROM, Quirks::default());
+        assert!(source.contains("fn block_0202(c: &mut Cpu, budget: &mut u32) -> u16 {"));
+        assert!(source.contains("if c.v()[0] == 0x05 { 0x0208 } else { 0x0206 }"));
+        assert!(source.contains("0x0202 if c.memory()[0x202..0x206] == ROM[0x002..0x006] => block_0202(c, budget),"));
+        assert!(!source.contains("block_020c"));
+    }
+
+    #[test]
+    fn test_long_addresses() {
+        let rom = [
+            0x30, 0x00, // 200: skip if V0 == 0
+            0xF0, 0x00, 0x02, 0x00, // 202: I = 0x0200
+            0x12, 0x00, // 206: jump 200
+        ];
+        assert_eq!(find_blocks(&rom), vec![
+            Block { start: 0x200, end: 0x202 },
+            Block { start: 0x202, end: 0x206 },
+            Block { start: 0x206, end: 0x208 },
+        ]);
+        let source = recompile(&rom, Quirks::default());
+        assert!(source.contains("if c.v()[0] == 0x00 { 0x0206 } else { 0x0202 }"));
+        assert!(source.contains("*c.index() = 0x0200;"));
+        assert!(!source.contains("// 0204"));
+    }
+
+    // Runs a program built from `source` against this crate, with `args`,
+    // and returns what it printed
+    fn run_recompiled(source: &str, args: &[&str]) -> String {
+        let manifest = env!("CARGO_MANIFEST_DIR");
+        let project = env::temp_dir().join(format!("chip8-recompiled-{}", process::id()));
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(project.join("Cargo.toml"), format!(
+            "[package]\nname = \"recompiled\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nchip8 = {{ path = {:?} }}\n\n[workspace]\n", manifest)).unwrap();
+        // the same dependency versions as this build, already downloaded
+        fs::copy(Path::new(manifest).join("Cargo.lock"), project.join("Cargo.lock")).unwrap();
+        fs::write(project.join("src/main.rs"), source).unwrap();
+
+        let target = env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| format!("{}/target", manifest));
+        let output = Command::new(env::var("CARGO").unwrap_or_else(|_| String::from("cargo")))
+            .args(["run", "--quiet", "--offline", "--target-dir"])
+            .arg(Path::new(&target).join("recompiled"))
+            .arg("--manifest-path").arg(project.join("Cargo.toml"))
+            .arg("--").args(args)
+            .output().unwrap();
+        fs::remove_dir_all(&project).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let rom = [
+            0x00, 0xE0, // 200: clear the screen
+            0xC0, 0x0F, // 202: V0 = a random digit
+            0xF0, 0x29, // 204: I = its font sprite
+            0xD1, 0x25, // 206: draw it at V1, V2
+            0x71, 0x05, // 208: V1 += 5
+            0x31, 0x41, // 20A: skip if V1 == 65
+            0x12, 0x02, // 20C: jump 202
+            0x61, 0x00, // 20E: V1 = 0
+            0x72, 0x06, // 210: V2 += 6
+            0x32, 0x1E, // 212: skip if V2 == 30
+            0x12, 0x02, // 214: jump 202
+            0x62, 0x00, // 216: V2 = 0
+            0x12, 0x00, // 218: jump 200
+        ];
+        let source = recompile(&rom, Quirks::default());
+        let args = ["--frames", "25", "--seed", "7"];
+        let recompiled = run_recompiled(&source, &args);
+        assert!(recompiled.contains('#'));
+        assert_eq!(recompiled, run_recompiled(&source, &[&args[..], &["--interpret"]].concat()));
+    }
+}
@@ -0,0 +1,284 @@
+use gif;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use framebuffer::Screen;
+use frontend;
+use palette::Rgb;
+use screenshot;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+// Browsers slow anything quicker than 2/100ths of a second down to 1/10th,
+// so a GIF frame is never shown for less than this.
+const MIN_GIF_DELAY: u32 = 2;
+
+// How hard to look for a frame's palette when the screen brings its own
+// colors, 1 (best) to 30 (quickest). Recording shouldn't stall the game.
+const QUANTIZE_SPEED: i32 = 10;
+
+enum Sink {
+    // and the size of its logical screen, which every frame is fitted into
+    Gif { encoder: gif::Encoder<BufWriter<File>>, size: (u32, u32) },
+    // numbered PNGs plus an ffmpeg concat list giving each one's duration
+    Png { dir: PathBuf, list: BufWriter<File>, count: u32 },
+}
+
+// Records one screen per 60 Hz frame. A screen is only written once the
+// next different one arrives, so identical frames collapse into one longer frame.
+pub struct Recorder {
+    sink: Sink,
+    scale: u32,
+    colors: Vec<Rgb>,
+    pending: Option<Screen>,
+    // how many frames the pending screen has been up for
+    held: u32,
+    // frames and centiseconds written so far, to keep GIF rounding from drifting
+    frames: u32,
+    centis: u32,
+}
+
+impl Recorder {
+    // size is the screen's, in CHIP-8 pixels, and scale the size of a
+    // pixel of CHIP-8's 64 pixel wide screen
+    pub fn gif(path: &Path, size: (usize, usize), scale: u32, colors: &[Rgb]) -> io::Result<Recorder> {
+        let palette = screenshot::rgb_bytes(colors);
+        let pixel = frontend::scale_for(scale, size.0);
+        let (width, height) = (size.0 as u32 * pixel, size.1 as u32 * pixel);
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &palette)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(Recorder::new(Sink::Gif { encoder, size: (width, height) }, scale, colors))
+    }
+
+    pub fn png_sequence(dir: &Path, scale: u32, colors: &[Rgb]) -> io::Result<Recorder> {
+        fs::create_dir_all(dir)?;
+        let list = BufWriter::new(File::create(dir.join("frames.txt"))?);
+        let sink = Sink::Png { dir: dir.to_path_buf(), list, count: 0 };
+        Ok(Recorder::new(sink, scale, colors))
+    }
+
+    fn new(sink: Sink, scale: u32, colors: &[Rgb]) -> Recorder {
+        Recorder {
+            sink,
+            scale,
+            colors: colors.to_vec(),
+            pending: None,
+            held: 0,
+            frames: 0,
+            centis: 0,
+        }
+    }
+
+    // Call once per emulated frame with whatever is on screen.
+    pub fn frame(&mut self, screen: &Screen) -> io::Result<()> {
+        match self.pending {
+            Some(ref pending) if pending == screen => (),
+            Some(_) if self.too_short() => {
+                // drop the screen in between rather than let a browser slow it down
+                self.pending = Some(screen.clone());
+            },
+            Some(_) => {
+                self.flush()?;
+                self.pending = Some(screen.clone());
+            },
+            None => self.pending = Some(screen.clone())
+        }
+        self.held += 1;
+        Ok(())
+    }
+
+    // Writes out the last screen and closes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        match self.sink {
+            Sink::Gif { encoder, .. } => encoder.into_inner().map_err(io::Error::other)?.flush(),
+            Sink::Png { mut list, .. } => list.flush()
+        }
+    }
+
+    fn too_short(&self) -> bool {
+        match self.sink {
+            Sink::Gif { .. } => self.delay() < MIN_GIF_DELAY,
+            Sink::Png { .. } => false
+        }
+    }
+
+    // centiseconds the pending screen should be shown for, rounded so that
+    // the total stays in step with the frames recorded so far
+    fn delay(&self) -> u32 {
+        let end = (self.frames + self.held) * 100 / FRAMES_PER_SECOND;
+        end - self.centis
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let screen = match self.pending.take() {
+            Some(screen) => screen,
+            None => return Ok(())
+        };
+
+        let delay = self.delay();
+        let scale = frontend::scale_for(self.scale, screen.width());
+        match self.sink {
+            Sink::Gif { ref mut encoder, size } => {
+                // a screen that changed size since recording started, after
+                // 00FF or 0011, is shrunk to fit and centered on the first one's
+                let scale = scale.min(size.0 / screen.width() as u32).min(size.1 / screen.height() as u32).max(1);
+                let width = screen.width() as u32 * scale;
+                let (logical_width, logical_height) = (size.0 as u16, size.1 as u16);
+                let mut frame = match screen.own_colors() {
+                    // a palette of its own for each frame, made to fit in 256 colors
+                    Some(own) => {
+                        let pixels = letterbox(&screenshot::scaled(&own, screen.width(), scale), width, size, (0, 0, 0));
+                        gif::Frame::from_rgb_speed(logical_width, logical_height, &screenshot::rgb_bytes(&pixels), QUANTIZE_SPEED)
+                    },
+                    None => {
+                        let pixels = letterbox(&screenshot::indexed(&screen, scale, self.colors.len()), width, size, 0);
+                        gif::Frame::from_indexed_pixels(logical_width, logical_height, &pixels, None)
+                    }
+                };
+                // a frame is shown for at most 65535 centiseconds, longer
+                // than that takes the same frame again
+                let mut left = delay;
+                loop {
+                    frame.delay = left.min(u16::MAX as u32) as u16;
+                    encoder.write_frame(&frame).map_err(io::Error::other)?;
+                    left -= frame.delay as u32;
+                    if left == 0 {
+                        break;
+                    }
+                }
+            },
+            Sink::Png { ref dir, ref mut list, ref mut count } => {
+                *count += 1;
+                let name = format!("frame_{:06}.png", count);
+                let file = BufWriter::new(File::create(dir.join(&name))?);
+                screenshot::write_png(file, &screen, scale, &self.colors)?;
+                writeln!(list, "file '{}'\nduration {:.6}", name, self.held as f64 / FRAMES_PER_SECOND as f64)?;
+            }
+        }
+
+        self.frames += self.held;
+        self.centis += delay;
+        self.held = 0;
+        Ok(())
+    }
+}
+
+// Rows of pixels `width` across in the middle of a canvas of `size`, the
+// rest of it filled in. Whatever doesn't fit is cut off.
+fn letterbox<T: Copy>(pixels: &[T], width: u32, size: (u32, u32), fill: T) -> Vec<T> {
+    let (width, canvas_width, canvas_height) = (width as usize, size.0 as usize, size.1 as usize);
+    let height = pixels.len() / width;
+    let (left, top) = (canvas_width.saturating_sub(width) / 2, canvas_height.saturating_sub(height) / 2);
+    let mut canvas = vec![fill; canvas_width * canvas_height];
+    for (y, row) in pixels.chunks(width).take(canvas_height).enumerate() {
+        let at = (top + y) * canvas_width + left;
+        let shown = width.min(canvas_width);
+        canvas[at..at + shown].copy_from_slice(&row[..shown]);
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use gif;
+
+    use recorder::{letterbox, Recorder};
+    use palette::Palette;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use framebuffer::Screen;
+
+    #[test]
+    fn test_png_sequence_collapses_frames() {
+        let dir = env::temp_dir().join(format!("chip8-recorder-{}", ::std::process::id()));
+        let mut recorder = Recorder::png_sequence(&dir, 1, Palette::default().colors()).unwrap();
+
+        let blank = Screen::default();
+        let mut dot = blank.clone();
+        dot[4][4] = 1;
+        for _ in 0..30 {
+            recorder.frame(&blank).unwrap();
+        }
+        for _ in 0..90 {
+            recorder.frame(&dot).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let list = fs::read_to_string(dir.join("frames.txt")).unwrap();
+        assert_eq!(list, "file 'frame_000001.png'\nduration 0.500000\nfile 'frame_000002.png'\nduration 1.500000\n");
+        assert!(dir.join("frame_000002.png").exists());
+        assert!(!dir.join("frame_000003.png").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_gif_delays_keep_time() {
+        let path = env::temp_dir().join(format!("chip8-recorder-{}.gif", ::std::process::id()));
+        let mut recorder = Recorder::gif(&path, (64, 32), 1, Palette::default().colors()).unwrap();
+
+        // every frame different: 60 frames have to come out as one second
+        let mut screen = Screen::default();
+        for frame in 0..60 {
+            screen[0][frame] = 1;
+            recorder.frame(&screen).unwrap();
+            assert!(recorder.held <= 2);
+        }
+        recorder.flush().unwrap();
+        assert_eq!(recorder.frames, 60);
+        assert_eq!(recorder.centis, 100);
+        recorder.finish().unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    // (width, height, delay) of each frame of a GIF
+    fn gif_frames(path: &Path) -> Vec<(u16, u16, u16)> {
+        let mut decoder = gif::DecodeOptions::new().read_info(fs::File::open(path).unwrap()).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.width, frame.height, frame.delay));
+        }
+        frames
+    }
+
+    #[test]
+    fn test_gif_fits_new_screen_sizes() {
+        let path = env::temp_dir().join(format!("chip8-recorder-resize-{}.gif", ::std::process::id()));
+        let mut recorder = Recorder::gif(&path, (64, 32), 2, Palette::default().colors()).unwrap();
+        for screen in &[Screen::default(), Screen::new(128, 64), Screen::new(256, 192)] {
+            for _ in 0..30 {
+                recorder.frame(screen).unwrap();
+            }
+        }
+        recorder.finish().unwrap();
+        assert_eq!(gif_frames(&path), vec![(128, 64, 50), (128, 64, 50), (128, 64, 50)]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_gif_splits_long_holds() {
+        let path = env::temp_dir().join(format!("chip8-recorder-hold-{}.gif", ::std::process::id()));
+        let mut recorder = Recorder::gif(&path, (64, 32), 1, Palette::default().colors()).unwrap();
+        // 700 seconds of the same screen
+        for _ in 0..700 * 60 {
+            recorder.frame(&Screen::default()).unwrap();
+        }
+        recorder.finish().unwrap();
+        assert_eq!(gif_frames(&path), vec![(64, 32, 65535), (64, 32, 4465)]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_letterbox() {
+        assert_eq!(letterbox(&[1, 2, 3, 4], 2, (4, 4), 0), vec![0, 0, 0, 0, 0, 1, 2, 0, 0, 3, 4, 0, 0, 0, 0, 0]);
+        // too big is cut off
+        assert_eq!(letterbox(&[1, 2, 3, 4, 5, 6], 3, (2, 1), 0), vec![1, 2]);
+    }
+}
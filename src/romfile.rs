@@ -0,0 +1,278 @@
+use miniz_oxide::inflate;
+
+// ROMs usually come as raw binaries, but archives and listings hold them too:
+// ZIP files, hex dumps typed in from magazines and Intel HEX files.
+
+// Extensions of the entries in an archive that are likely ROMs. Many old
+// ROMs have no extension at all.
+const ROM_EXTENSIONS: [&str; 9] = ["ch8", "c8", "sc8", "xo8", "c8x", "rom", "bin", "hex", "ihx"];
+
+pub fn is_zip(file: &[u8]) -> bool {
+    file.starts_with(b"PK\x03\x04") || file.starts_with(b"PK\x05\x06")
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<usize, String> {
+    data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize).ok_or_else(truncated)
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<usize, String> {
+    data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize).ok_or_else(truncated)
+}
+
+fn truncated() -> String {
+    String::from("The archive is cut short")
+}
+
+struct Entry {
+    name: String,
+    method: usize,
+    encrypted: bool,
+    compressed_size: usize,
+    size: usize,
+    // where the entry's local header is
+    offset: usize,
+}
+
+// The files in a ZIP archive, read through its central directory
+pub struct Zip<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+impl<'a> Zip<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Zip<'a>, String> {
+        // the end of central directory record, which a comment may follow
+        let end = (0..data.len().saturating_sub(21)).rev()
+            .find(|&at| data[at..].starts_with(b"PK\x05\x06"))
+            .ok_or("Not a ZIP archive")?;
+        let count = u16_at(data, end + 10)?;
+        let mut at = u32_at(data, end + 16)?;
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            if !data.get(at..).is_some_and(|header| header.starts_with(b"PK\x01\x02")) {
+                return Err(String::from("The archive's directory is damaged"));
+            }
+            let name_length = u16_at(data, at + 28)?;
+            let name = data.get(at + 46..at + 46 + name_length).ok_or_else(truncated)?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(data, at + 10)?,
+                encrypted: u16_at(data, at + 8)? & 1 != 0,
+                compressed_size: u32_at(data, at + 20)?,
+                size: u32_at(data, at + 24)?,
+                offset: u32_at(data, at + 42)?,
+            });
+            at += 46 + name_length + u16_at(data, at + 30)? + u16_at(data, at + 32)?;
+        }
+        Ok(Zip { data, entries })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    // The entries that look like ROMs, skipping folders, documentation and
+    // the metadata macOS adds
+    pub fn roms(&self) -> Vec<&str> {
+        self.names().into_iter().filter(|name| {
+            let file = name.rsplit('/').next().unwrap_or(name);
+            let extension = file.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+            !file.is_empty() && !name.starts_with("__MACOSX/") && !file.starts_with('.')
+                && extension.is_none_or(|extension| ROM_EXTENSIONS.contains(&extension.as_str()))
+        }).collect()
+    }
+
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, String> {
+        let entry = self.entries.iter().find(|entry| entry.name == name)
+            .ok_or_else(|| format!("The archive has no {}", name))?;
+        if entry.encrypted {
+            return Err(format!("{} is encrypted", name));
+        }
+
+        let header = entry.offset;
+        if !self.data.get(header..).is_some_and(|local| local.starts_with(b"PK\x03\x04")) {
+            return Err(format!("{} is damaged", name));
+        }
+        let start = header + 30 + u16_at(self.data, header + 26)? + u16_at(self.data, header + 28)?;
+        let compressed = self.data.get(start..start + entry.compressed_size).ok_or_else(truncated)?;
+
+        let contents = match entry.method {
+            0 => compressed.to_vec(),
+            8 => inflate::decompress_to_vec_with_limit(compressed, entry.size).map_err(|e| format!("{} is damaged: {:?}", name, e.status))?,
+            method => return Err(format!("{} uses compression method {}, which isn't supported", name, method))
+        };
+        if contents.len() != entry.size {
+            return Err(format!("{} is damaged", name));
+        }
+        Ok(contents)
+    }
+}
+
+// The ROM in a text file, None for anything that isn't one
+pub fn decode_text(file: &[u8]) -> Option<Result<Vec<u8>, String>> {
+    let text = ::std::str::from_utf8(file).ok()?;
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty()).peekable();
+    if lines.peek()?.starts_with(':') && lines.all(|line| line.starts_with(':')) {
+        return Some(intel_hex(text));
+    }
+    hex_dump(text).map(Ok)
+}
+
+// Bytes as hex separated by white space, "00 E0 A2 2A" or "00E0 A22A". A
+// "0200:" at the start of a line is taken as an address and skipped, and so
+// is a first column without the colon when each line's first word is the
+// address the line before it ends at.
+fn hex_dump(text: &str) -> Option<Vec<u8>> {
+    let lines: Vec<Vec<&str>> = text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .filter(|words| !words.is_empty())
+        .collect();
+    let numbered = lines.len() > 1 && lines.windows(2).all(|pair| {
+        let length = pair[0][1..].iter().map(|word| word.len() / 2).sum::<usize>();
+        address(pair[0][0]).is_some_and(|at| address(pair[1][0]) == Some(at + length))
+    });
+    let mut rom = Vec::new();
+    for words in &lines {
+        let skip = numbered || words[0].ends_with(':');
+        for word in &words[skip as usize..] {
+            if word.len() % 2 != 0 || !word.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            for at in (0..word.len()).step_by(2) {
+                rom.push(u8::from_str_radix(&word[at..at + 2], 16).ok()?);
+            }
+        }
+    }
+    if rom.is_empty() { None } else { Some(rom) }
+}
+
+// The address at the start of a hex dump line, with or without its colon
+fn address(word: &str) -> Option<usize> {
+    usize::from_str_radix(word.trim_end_matches(':'), 16).ok()
+}
+
+// Intel HEX records, ":LLAAAATT<data>CC". Programs placed at 0x200 or above
+// are taken to start at 0x200, the way CHIP-8 loads them.
+fn intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut base = 0;
+    let mut writes = Vec::new();
+    for (number, line) in text.lines().map(str::trim).enumerate().filter(|(_, line)| !line.is_empty()) {
+        let error = |what: &str| format!("Line {}: {}", number + 1, what);
+        let digits = &line[1..];
+        if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(error("not a hex record"));
+        }
+        let record: Vec<u8> = (0..digits.len()).step_by(2)
+            .map(|at| u8::from_str_radix(&digits[at..at + 2], 16).unwrap_or(0))
+            .collect();
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(error("wrong length"));
+        }
+        if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(error("bad checksum"));
+        }
+
+        let address = (record[1] as usize) << 8 | record[2] as usize;
+        let data = &record[4..record.len() - 1];
+        let value = || data.iter().fold(0, |value, &byte| value << 8 | byte as usize);
+        match record[3] {
+            0x00 => writes.extend(data.iter().enumerate().map(|(i, &byte)| (base + address + i, byte))),
+            0x01 => break,
+            // extended segment and linear addresses
+            0x02 => base = value() << 4,
+            0x04 => base = value() << 16,
+            // start addresses, which CHIP-8 has no use for
+            0x03 | 0x05 => (),
+            kind => return Err(error(&format!("unknown record type {:02X}", kind)))
+        }
+    }
+
+    let lowest = writes.iter().map(|&(address, _)| address).min().ok_or("The file has no data")?;
+    let start = if lowest >= 0x200 { 0x200 } else { 0 };
+    let end = writes.iter().map(|&(address, _)| address + 1).max().unwrap_or(start);
+    if end - start > 0x10000 {
+        return Err(String::from("The program is too big"));
+    }
+    let mut rom = vec![0; end - start];
+    for (address, byte) in writes {
+        rom[address - start] = byte;
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use miniz_oxide::deflate;
+
+    use romfile::{decode_text, is_zip, Zip};
+
+    // A ZIP archive of (name, contents, deflated?) entries
+    fn archive(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let (mut data, mut directory) = (Vec::new(), Vec::new());
+        for &(name, contents, deflated) in files {
+            let stored = if deflated { deflate::compress_to_vec(contents, 6) } else { contents.to_vec() };
+            let mut fields = vec![20, 0, 0, 0, if deflated { 8 } else { 0 }, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            fields.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0, 0]);
+
+            directory.extend_from_slice(b"PK\x01\x02\x14\x00");
+            directory.extend_from_slice(&fields);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+
+            data.extend_from_slice(b"PK\x03\x04");
+            data.extend_from_slice(&fields);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&stored);
+        }
+        let offset = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_zip() {
+        let brix = [0x6E, 0x05, 0x65, 0x00, 0x6B, 0x06, 0x6A, 0x00].repeat(20);
+        let file = archive(&[("readme.txt", b"Have fun", false), ("games/", b"", false),
+                             ("games/BRIX", &brix, true), ("games/pong.ch8", b"\x00\xE0", false)]);
+        assert!(is_zip(&file));
+        let zip = Zip::parse(&file).unwrap();
+        assert_eq!(zip.names().len(), 4);
+        assert_eq!(zip.roms(), vec!["games/BRIX", "games/pong.ch8"]);
+        assert_eq!(zip.read("games/BRIX").unwrap(), brix);
+        assert_eq!(zip.read("games/pong.ch8").unwrap(), vec![0x00, 0xE0]);
+        assert!(zip.read("games/tetris.ch8").is_err());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = b"0200: 00 E0 a2 2a\n       600C 6108\n";
+        assert_eq!(decode_text(dump), Some(Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08])));
+        // addresses without the colon, counting on from line to line
+        let dump = b"0200 00 E0 A2 2A\n0204 600C 6108\n";
+        assert_eq!(decode_text(dump), Some(Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08])));
+        let dump = b"00E0 A22A\n600C 6108\n";
+        assert_eq!(decode_text(dump), Some(Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08])));
+        // a raw ROM, even one that happens to be text
+        assert_eq!(decode_text(&[0x00, 0xE0, 0x12, 0x00]), None);
+        assert_eq!(decode_text(b"HELLO WORLD"), None);
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let file = b":0402000000E0A22A4E\n:02020800600C88\n:00000001FF\n";
+        assert_eq!(decode_text(file), Some(Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0, 0, 0, 0, 0x60, 0x0C])));
+        let corrupt = b":0402000000E0A22A4F\n";
+        assert_eq!(decode_text(corrupt), Some(Err(String::from("Line 1: bad checksum"))));
+    }
+}
@@ -0,0 +1,85 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use sha1;
+
+// How many flags Fx75/Fx85 reach. The HP 48 had 8, XO-CHIP allows 16.
+pub const FLAG_COUNT: usize = 16;
+
+// Where this emulator keeps what it saves between runs: $XDG_DATA_HOME or
+// ~/.local/share on Linux, and the usual places elsewhere.
+pub fn data_dir() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| home().map(|home| home.join(".local").join("share")))
+    };
+    base.map(|base| base.join("chip8"))
+}
+
+// The SUPER-CHIP RPL user flags of one ROM, which games keep high scores
+// in. On the calculator they outlived the game, so they're kept in a file
+// named after the ROM's SHA-1.
+pub struct FlagFile {
+    path: PathBuf,
+}
+
+impl FlagFile {
+    // None when there's nowhere to keep it
+    pub fn for_rom(rom: &[u8]) -> Option<FlagFile> {
+        data_dir().map(|dir| FlagFile::new(dir.join("flags").join(format!("{}.bin", sha1::hex_digest(rom)))))
+    }
+
+    pub fn new(path: PathBuf) -> FlagFile {
+        FlagFile { path }
+    }
+
+    // None if the ROM hasn't saved any yet
+    pub fn load(&self) -> io::Result<Option<[u8; FLAG_COUNT]>> {
+        let mut saved = Vec::new();
+        match File::open(&self.path).and_then(|mut file| file.read_to_end(&mut saved)) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        }
+        let mut flags = [0; FLAG_COUNT];
+        for (flag, &value) in flags.iter_mut().zip(saved.iter()) {
+            *flag = value;
+        }
+        Ok(Some(flags))
+    }
+
+    pub fn save(&self, flags: [u8; FLAG_COUNT]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use rplflags::FlagFile;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = env::temp_dir().join(format!("chip8-flags-{}", ::std::process::id()));
+        let file = FlagFile::new(dir.join("flags").join("game.bin"));
+        assert!(file.load().unwrap().is_none());
+
+        let mut flags = [0; 16];
+        flags[0] = 0x12;
+        flags[7] = 0x34;
+        file.save(flags).unwrap();
+        assert_eq!(file.load().unwrap(), Some(flags));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use png;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use framebuffer::Screen;
+use palette::Rgb;
+
+// Each CHIP-8 pixel blown up to scale x scale. Indexed, unless the screen
+// brings its own colors (CHIP-8X, MegaChip), which are written as they are.
+pub fn write_png<W: Write>(out: W, screen: &Screen, scale: u32, colors: &[Rgb]) -> io::Result<()> {
+    let width = screen.width() as u32 * scale;
+    let height = screen.height() as u32 * scale;
+
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data = match screen.own_colors() {
+        Some(own) => {
+            encoder.set_color(png::ColorType::Rgb);
+            rgb_bytes(&scaled(&own, screen.width(), scale))
+        },
+        None => {
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_palette(rgb_bytes(colors));
+            indexed(screen, scale, colors.len())
+        }
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+// The screen as one palette index per host pixel, row by row.
+pub fn indexed(screen: &Screen, scale: u32, colors: usize) -> Vec<u8> {
+    let last = colors as u8 - 1;
+    let pixels: Vec<u8> = screen.pixels().iter().map(|&pixel| pixel.min(last)).collect();
+    scaled(&pixels, screen.width(), scale)
+}
+
+// Rows width values long, each value blown up to scale x scale
+pub fn scaled<T: Copy>(values: &[T], width: usize, scale: u32) -> Vec<T> {
+    let scale = scale as usize;
+    let mut data = Vec::with_capacity(values.len() * scale * scale);
+    for row in values.chunks(width) {
+        let line: Vec<T> = row.iter()
+            .flat_map(|&value| ::std::iter::repeat_n(value, scale))
+            .collect();
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+    data
+}
+
+// r, g, b, r, g, b, ...
+pub fn rgb_bytes(colors: &[Rgb]) -> Vec<u8> {
+    colors.iter().flat_map(|&(r, g, b)| vec![r, g, b]).collect()
+}
+
+// A binary (P4) PBM of the raw framebuffer, one bit per pixel, 1 is lit.
+pub fn write_pbm<W: Write>(mut out: W, screen: &Screen) -> io::Result<()> {
+    write!(out, "P4\n{} {}\n", screen.width(), screen.height())?;
+    for row in screen.rows() {
+        // rows are padded out to a whole byte
+        let packed: Vec<u8> = row.chunks(8)
+            .map(|bits| bits.iter()
+                .enumerate()
+                .fold(0, |byte, (bit, &pixel)| byte | (((pixel != 0) as u8) << (7 - bit))))
+            .collect();
+        out.write_all(&packed)?;
+    }
+    Ok(())
+}
+
+// An SVG with one rect per horizontal run of same colored pixels, which
+// keeps the files small enough to paste into docs.
+pub fn write_svg<W: Write>(mut out: W, screen: &Screen, scale: u32, colors: &[Rgb]) -> io::Result<()> {
+    let (width, height) = (screen.width() as u32 * scale, screen.height() as u32 * scale);
+    writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">",
+             width, height, screen.width(), screen.height())?;
+
+    // the whole screen in the color of an unlit pixel, with the rest on top
+    let rgb = screen.rgb(colors);
+    let background = rgb[screen.pixels().iter().position(|&pixel| pixel == 0).unwrap_or(0)];
+    let (r, g, b) = background;
+    writeln!(out, "<rect width=\"{}\" height=\"{}\" fill=\"#{:02x}{:02x}{:02x}\"/>", screen.width(), screen.height(), r, g, b)?;
+
+    for (y, row) in rgb.chunks(screen.width()).enumerate() {
+        let mut x = 0;
+        while x < row.len() {
+            let color = row[x];
+            let run = row[x..].iter().take_while(|&&c| c == color).count();
+            if color != background {
+                let (r, g, b) = color;
+                writeln!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"1\" fill=\"#{:02x}{:02x}{:02x}\"/>", x, y, run, r, g, b)?;
+            }
+            x += run;
+        }
+    }
+
+    writeln!(out, "</svg>")
+}
+
+// Writes <stem>.png, <stem>.pbm and <stem>.svg
+pub fn save(stem: &Path, screen: &Screen, scale: u32, colors: &[Rgb]) -> io::Result<()> {
+    write_png(BufWriter::new(File::create(stem.with_extension("png"))?), screen, scale, colors)?;
+    write_pbm(BufWriter::new(File::create(stem.with_extension("pbm"))?), screen)?;
+    write_svg(BufWriter::new(File::create(stem.with_extension("svg"))?), screen, scale, colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::{Palette, CHIP8X_COLORS};
+    use framebuffer::{Framebuffer, Screen};
+    use screenshot::{write_pbm, write_png, write_svg};
+
+    fn screen() -> Screen {
+        let mut screen = Screen::default();
+        screen[0][0] = 1;
+        screen[0][9] = 1;
+        screen[1][2] = 1;
+        screen[1][3] = 1;
+        screen
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut out = Vec::new();
+        write_pbm(&mut out, &screen()).unwrap();
+        let header = b"P4\n64 32\n";
+        assert_eq!(&out[..header.len()], header);
+        let data = &out[header.len()..];
+        assert_eq!(data.len(), 8 * 32);
+        assert_eq!(&data[0..2], &[0x80, 0x40]);
+        assert_eq!(data[8], 0x30);
+    }
+
+    #[test]
+    fn test_pbm_size() {
+        let mut out = Vec::new();
+        write_pbm(&mut out, &Screen::new(64, 48)).unwrap();
+        assert!(out.starts_with(b"P4\n64 48\n"));
+        assert_eq!(out.len(), b"P4\n64 48\n".len() + 8 * 48);
+    }
+
+    #[test]
+    fn test_svg() {
+        let mut out = Vec::new();
+        write_svg(&mut out, &screen(), 20, Palette::default().colors()).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"1280\" height=\"640\""));
+        // the two pixels on row 1 become one rect
+        assert!(svg.contains("<rect x=\"2\" y=\"1\" width=\"2\" height=\"1\" fill=\"#00fa00\"/>"));
+        assert_eq!(svg.matches("<rect").count(), 4);
+    }
+
+    #[test]
+    fn test_png() {
+        let mut out = Vec::new();
+        write_png(&mut out, &screen(), 2, Palette::default().colors()).unwrap();
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+        // IHDR width and height
+        assert_eq!(&out[16..24], &[0, 0, 0, 128, 0, 0, 0, 64]);
+    }
+
+    #[test]
+    fn test_own_colors() {
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.enable_colors();
+        framebuffer.toggle(0, 0, 1);
+        framebuffer.colors_mut().unwrap().fill(0..1, 0..4, 5);
+        let screen = framebuffer.pixels();
+
+        // CHIP-8X's colors rather than the palette's
+        let mut out = Vec::new();
+        write_svg(&mut out, screen, 1, Palette::default().colors()).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        let (r, g, b) = CHIP8X_COLORS[5];
+        assert!(svg.contains(&format!("<rect x=\"0\" y=\"0\" width=\"1\" height=\"1\" fill=\"#{:02x}{:02x}{:02x}\"/>", r, g, b)));
+
+        // as RGB
+        let mut out = Vec::new();
+        write_png(&mut out, screen, 1, Palette::default().colors()).unwrap();
+        assert_eq!(out[25], 2);
+    }
+}
@@ -0,0 +1,72 @@
+use sdl2;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+// Samples a second out of the speaker
+pub const SAMPLE_RATE: u32 = 22050;
+
+// Don't let more than this many 60ths of a second queue up, or the sound
+// falls further and further behind the picture
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+// The pitch of the VIP's beeper, which sounds while Q is on
+const TONE_FREQUENCY: u32 = 1400;
+
+// Plays MegaChip's samples and the VIP's beeper through SDL, 8 bit unsigned mono.
+pub struct Speaker {
+    queue: AudioQueue<u8>,
+    // samples into the beeper's square wave, so one frame carries on from the last
+    phase: u32,
+}
+
+impl Speaker {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Speaker, String> {
+        let audio = sdl_context.audio()?;
+        let spec = AudioSpecDesired { freq: Some(SAMPLE_RATE as i32), channels: Some(1), samples: None };
+        let queue = audio.open_queue::<u8, _>(None, &spec)?;
+        queue.resume();
+        Ok(Speaker { queue, phase: 0 })
+    }
+
+    // Queues a frame's worth of sound, unless plenty is waiting already
+    pub fn play(&mut self, samples: &[u8]) {
+        if self.queue.size() < MAX_QUEUED_FRAMES * SAMPLE_RATE / 60 {
+            self.queue.queue(samples);
+        }
+    }
+
+    // A 60th of a second of the beeper
+    pub fn beep(&mut self) {
+        let mut samples = vec![0; (SAMPLE_RATE / 60) as usize];
+        square_wave(&mut samples, &mut self.phase);
+        self.play(&samples);
+    }
+}
+
+// Fills out with a square wave at TONE_FREQUENCY, starting phase samples in
+fn square_wave(out: &mut [u8], phase: &mut u32) {
+    let period = SAMPLE_RATE / TONE_FREQUENCY;
+    for value in out.iter_mut() {
+        *value = if *phase < period / 2 { 0xC0 } else { 0x40 };
+        *phase = (*phase + 1) % period;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use speaker::{square_wave, SAMPLE_RATE, TONE_FREQUENCY};
+
+    #[test]
+    fn test_square_wave_carries_on() {
+        let period = (SAMPLE_RATE / TONE_FREQUENCY) as usize;
+        let mut phase = 0;
+        let mut first = vec![0; period / 2 + 1];
+        square_wave(&mut first, &mut phase);
+        assert!(first[..period / 2].iter().all(|&value| value == 0xC0));
+        assert_eq!(first[period / 2], 0x40);
+        // the next frame picks up halfway through the low half
+        let mut next = vec![0; period];
+        square_wave(&mut next, &mut phase);
+        assert_eq!(next[period - period / 2 - 2], 0x40);
+        assert_eq!(next[period - period / 2 - 1], 0xC0);
+    }
+}
@@ -0,0 +1,352 @@
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers,
+                       KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use sdl2::keyboard::Keycode;
+
+use std::collections::VecDeque;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use framebuffer::Screen;
+use frontend::{Hotkey, Input, Renderer};
+use keymap::{HostKeys, KeyMap};
+use palette::{Palette, Rgb};
+
+// Most terminals only report presses, so a key counts as held until it
+// hasn't been seen for a while. Long enough to bridge the gap before the
+// OS starts auto-repeating.
+const HOLD_TIME: Duration = Duration::from_millis(300);
+
+// Messages kept under the picture, enough for the title, the detected
+// platform and the key hint that come at startup
+const STATUS_LINES: usize = 3;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CellMode {
+    // one cell is two pixels stacked, drawn as an upper half block: 64x16 cells
+    HalfBlock,
+    // one cell is a 2x4 braille pattern: 32x8 cells, single color
+    Braille,
+}
+
+// Runs the emulator inside a terminal: raw mode input and Unicode block output.
+pub struct Terminal {
+    out: Stdout,
+    mode: CellMode,
+    palette: Palette,
+    keymap: KeyMap,
+    last_seen: [Option<Instant>; 16],
+    // true if the terminal tells us about key releases (kitty protocol)
+    releases: bool,
+    held: [bool; 16],
+    hotkeys: Vec<Hotkey>,
+    // the last few messages, shown on the lines under the picture
+    status: VecDeque<String>,
+    // how many lines the picture took up last time
+    lines: u16,
+}
+
+impl Terminal {
+    pub fn new(keymap: KeyMap, mode: CellMode, palette: Palette) -> io::Result<Terminal> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        Ok(Terminal {
+            out,
+            mode,
+            palette,
+            keymap,
+            last_seen: [None; 16],
+            releases,
+            held: [false; 16],
+            hotkeys: Vec::new(),
+            status: VecDeque::new(),
+            lines: 0,
+        })
+    }
+
+    fn render(&mut self, screen: &Screen) -> io::Result<()> {
+        let rgb = screen.rgb(self.palette.colors());
+        queue!(self.out, MoveTo(0, 0))?;
+        // only emit colors when they change, it's a lot less to send over SSH
+        let mut colors = None;
+        match self.mode {
+            CellMode::HalfBlock => {
+                for row in 0..screen.height().div_ceil(2) {
+                    for x in 0..screen.width() {
+                        let cell = half_block(&rgb, screen.width(), x, row);
+                        if colors != Some(cell) {
+                            queue!(self.out, SetForegroundColor(color(cell.0)), SetBackgroundColor(color(cell.1)))?;
+                            colors = Some(cell);
+                        }
+                        queue!(self.out, Print('▀'))?;
+                    }
+                    queue!(self.out, ResetColor, Print("\r\n"))?;
+                    colors = None;
+                }
+            },
+            CellMode::Braille => {
+                for row in 0..screen.height().div_ceil(4) {
+                    for col in 0..screen.width().div_ceil(2) {
+                        let cell = braille_colors(screen, &rgb, col, row);
+                        if colors != Some(cell) {
+                            queue!(self.out, SetForegroundColor(color(cell.0)), SetBackgroundColor(color(cell.1)))?;
+                            colors = Some(cell);
+                        }
+                        queue!(self.out, Print(braille(screen, col, row)))?;
+                    }
+                    queue!(self.out, ResetColor, Print("\r\n"))?;
+                    colors = None;
+                }
+            }
+        }
+        self.lines = match self.mode {
+            CellMode::HalfBlock => screen.height().div_ceil(2),
+            CellMode::Braille => screen.height().div_ceil(4)
+        } as u16;
+        self.draw_status()
+    }
+
+    // Printing would scroll the picture away, so messages go on the lines under it
+    fn draw_status(&mut self) -> io::Result<()> {
+        for (line, text) in self.status.iter().enumerate() {
+            queue!(self.out, MoveTo(0, self.lines + line as u16), Clear(ClearType::CurrentLine), Print(text))?;
+        }
+        self.out.flush()
+    }
+
+    fn poll(&mut self) -> io::Result<bool> {
+        let now = Instant::now();
+        while event::poll(Duration::from_secs(0))? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue
+            };
+
+            let quit = key.code == KeyCode::Esc
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+            if quit {
+                return Ok(false);
+            }
+
+            if let Some(hotkey) = hotkey(key.code) {
+                if key.kind == KeyEventKind::Press {
+                    self.hotkeys.push(hotkey);
+                }
+                continue;
+            }
+
+            let keycode = match to_keycode(key.code) {
+                Some(keycode) => keycode,
+                None => continue
+            };
+            let pressed = self.keymap.pressed(&HostKeys { keycodes: vec![keycode], ..Default::default() });
+            for hex in (0..16).filter(|&hex| pressed[hex]) {
+                match key.kind {
+                    KeyEventKind::Release => self.held[hex] = false,
+                    _ => {
+                        self.held[hex] = true;
+                        self.last_seen[hex] = Some(now);
+                    }
+                }
+            }
+        }
+
+        if !self.releases {
+            for hex in 0..16 {
+                let stale = self.last_seen[hex].is_none_or(|seen| now.duration_since(seen) > HOLD_TIME);
+                if stale {
+                    self.held[hex] = false;
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Renderer for Terminal {
+    fn draw(&mut self, screen: &Screen) {
+        // a broken pipe over SSH shouldn't take the emulator down mid-frame
+        let _ = self.render(screen);
+    }
+
+    fn palette(&self) -> Palette {
+        self.palette.clone()
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn message(&mut self, text: &str) {
+        if self.status.len() == STATUS_LINES {
+            self.status.pop_front();
+        }
+        self.status.push_back(text.replace('\n', " "));
+        let _ = self.draw_status();
+    }
+}
+
+impl Input for Terminal {
+    fn get_keys(&mut self) -> Option<[bool; 16]> {
+        match self.poll() {
+            Ok(true) => Some(self.held),
+            _ => None
+        }
+    }
+
+    fn hotkeys(&mut self) -> Vec<Hotkey> {
+        ::std::mem::take(&mut self.hotkeys)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn color((r, g, b): Rgb) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+// the (top, bottom) colors shown by half block cell x, row of a screen
+// width pixels wide, black below the bottom
+fn half_block(rgb: &[Rgb], width: usize, x: usize, row: usize) -> (Rgb, Rgb) {
+    let at = |y: usize| rgb.get(y * width + x).cloned().unwrap_or((0, 0, 0));
+    (at(row * 2), at(row * 2 + 1))
+}
+
+// A braille cell has one color for its dots and one for the rest: those
+// of the first lit and first unlit pixel in it
+fn braille_colors(screen: &Screen, rgb: &[Rgb], col: usize, row: usize) -> (Rgb, Rgb) {
+    let (mut on, mut off) = (None, None);
+    for dy in 0..4 {
+        for dx in 0..2 {
+            let (x, y) = (col * 2 + dx, row * 4 + dy);
+            if x >= screen.width() || y >= screen.height() {
+                continue;
+            }
+            let slot = if screen.get(x, y) != 0 { &mut on } else { &mut off };
+            slot.get_or_insert(rgb[y * screen.width() + x]);
+        }
+    }
+    let off = off.or(on).unwrap_or((0, 0, 0));
+    (on.unwrap_or(off), off)
+}
+
+// Braille dots are numbered down the left column then the right, with the
+// bottom row added later on, hence the odd bit order.
+fn braille(screen: &Screen, col: usize, row: usize) -> char {
+    const DOTS: [[u32; 2]; 4] = [
+        [0x01, 0x08],
+        [0x02, 0x10],
+        [0x04, 0x20],
+        [0x40, 0x80],
+    ];
+
+    let mut bits = 0;
+    for (dy, dots) in DOTS.iter().enumerate() {
+        for (dx, dot) in dots.iter().enumerate() {
+            if screen.get(col * 2 + dx, row * 4 + dy) != 0 {
+                bits |= dot;
+            }
+        }
+    }
+    ::std::char::from_u32(0x2800 + bits).unwrap()
+}
+
+fn hotkey(code: KeyCode) -> Option<Hotkey> {
+    match code {
+        KeyCode::F(6) => Some(Hotkey::Pause),
+        KeyCode::F(7) => Some(Hotkey::NextPalette),
+        KeyCode::F(9) => Some(Hotkey::RecordGif),
+        KeyCode::F(10) => Some(Hotkey::RecordFrames),
+        KeyCode::F(12) => Some(Hotkey::Screenshot),
+        _ => None
+    }
+}
+
+// Terminal keys are translated to SDL keycodes so the same bindings file
+// works in both frontends. Scancode bindings have no terminal equivalent.
+fn to_keycode(code: KeyCode) -> Option<Keycode> {
+    match code {
+        KeyCode::Char(c) if c.is_ascii() => Keycode::from_i32(c.to_ascii_lowercase() as i32),
+        KeyCode::Up => Some(Keycode::Up),
+        KeyCode::Down => Some(Keycode::Down),
+        KeyCode::Left => Some(Keycode::Left),
+        KeyCode::Right => Some(Keycode::Right),
+        KeyCode::Enter => Some(Keycode::Return),
+        KeyCode::Tab => Some(Keycode::Tab),
+        KeyCode::Backspace => Some(Keycode::Backspace),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use terminal::{braille, braille_colors, half_block, to_keycode};
+    use crossterm::event::KeyCode;
+    use sdl2::keyboard::Keycode;
+    use framebuffer::{Framebuffer, Screen};
+    use palette::{Palette, CHIP8X_COLORS};
+
+    #[test]
+    fn test_half_block() {
+        let mut screen = Screen::default();
+        screen[3][5] = 1;
+        let palette = Palette::default();
+        let (off, on) = (palette.color(0), palette.color(1));
+        let rgb = screen.rgb(palette.colors());
+        assert_eq!(half_block(&rgb, 64, 5, 1), (off, on));
+        assert_eq!(half_block(&rgb, 64, 5, 0), (off, off));
+    }
+
+    #[test]
+    fn test_own_colors() {
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.enable_colors();
+        framebuffer.toggle(0, 0, 1);
+        framebuffer.colors_mut().unwrap().fill(0..1, 0..4, 5);
+        let screen = framebuffer.pixels();
+        let rgb = screen.rgb(Palette::default().colors());
+        assert_eq!(half_block(&rgb, 64, 0, 0), (CHIP8X_COLORS[5], CHIP8X_COLORS[2]));
+        assert_eq!(braille_colors(screen, &rgb, 0, 0), (CHIP8X_COLORS[5], CHIP8X_COLORS[2]));
+        // no lit pixels: both the background
+        assert_eq!(braille_colors(screen, &rgb, 1, 0), (CHIP8X_COLORS[2], CHIP8X_COLORS[2]));
+    }
+
+    #[test]
+    fn test_braille() {
+        let mut screen = Screen::default();
+        assert_eq!(braille(&screen, 0, 0), '⠀');
+        screen[0][0] = 1;
+        screen[3][1] = 1;
+        assert_eq!(braille(&screen, 0, 0), '⢁');
+        for y in 4..8 {
+            screen[y][2] = 1;
+            screen[y][3] = 1;
+        }
+        assert_eq!(braille(&screen, 1, 1), '⣿');
+    }
+
+    #[test]
+    fn test_to_keycode() {
+        assert_eq!(to_keycode(KeyCode::Char('Q')), Some(Keycode::Q));
+        assert_eq!(to_keycode(KeyCode::Char('1')), Some(Keycode::Num1));
+        assert_eq!(to_keycode(KeyCode::Left), Some(Keycode::Left));
+    }
+}
@@ -0,0 +1,232 @@
+use cdp1802::{Bus, Cdp1802};
+use framebuffer::Screen;
+
+use CHIP8_HEIGHT;
+use CHIP8_WIDTH;
+
+// The COSMAC VIP itself rather than CHIP-8: a CDP1802 running the monitor
+// ROM and whatever interpreter was loaded, with the CDP1861 "Pixie" showing
+// memory by DMA. Games then run exactly as fast and as oddly as they did.
+
+// RAM from 0x0000, mirrored up to the monitor ROM at 0x8000
+pub const RAM_SIZE: usize = 4096;
+pub const MONITOR_SIZE: usize = 512;
+const MONITOR_START: u16 = 0x8000;
+
+// The CDP1861 draws 262 lines a frame, each 14 machine cycles long. It asks
+// for an interrupt two lines before its 128 lines of picture, takes 8 bytes
+// by DMA at the start of each of those, and raises EF1 for the 4 lines before
+// the picture starts and before it ends.
+const LINE_CYCLES: u32 = 14;
+const LINES: u32 = 262;
+const FIRST_LINE: u32 = 64;
+const PICTURE_LINES: usize = 128;
+const INTERRUPT_LINES: (u32, u32) = (FIRST_LINE - 2, FIRST_LINE);
+const DMA_BYTES: usize = 8;
+
+// Everything on the board but the processor
+struct Board {
+    ram: [u8; RAM_SIZE],
+    monitor: [u8; MONITOR_SIZE],
+    // after a reset the ROM shows up at 0x0000 too, until the first time the
+    // processor reaches up to 0x8000
+    monitor_low: bool,
+    keys: [bool; 16],
+    // the key OUT 2 picked for EF3 to report on
+    latched_key: u8,
+    display_on: bool,
+    line: u32,
+}
+
+impl Bus for Board {
+    fn read(&mut self, address: u16) -> u8 {
+        if address >= MONITOR_START {
+            self.monitor_low = false;
+        }
+        if address >= MONITOR_START || self.monitor_low {
+            self.monitor[address as usize % MONITOR_SIZE]
+        } else {
+            self.ram[address as usize % RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address < MONITOR_START {
+            self.ram[address as usize % RAM_SIZE] = value;
+        }
+    }
+
+    // INP 1 turns the display on and OUT 1 off
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.latched_key = value & 0xF,
+            _ => ()
+        }
+    }
+
+    fn flag(&self, n: u8) -> bool {
+        match n {
+            1 => {
+                let line = self.line;
+                (FIRST_LINE - 4..FIRST_LINE).contains(&line)
+                    || (FIRST_LINE + PICTURE_LINES as u32 - 4..FIRST_LINE + PICTURE_LINES as u32).contains(&line)
+            },
+            3 => self.keys[self.latched_key as usize],
+            _ => false
+        }
+    }
+}
+
+pub struct Vip {
+    cpu: Cdp1802,
+    board: Board,
+    // the last frame the CDP1861 showed, 64 pixels by 128 lines
+    picture: [[u8; CHIP8_WIDTH]; PICTURE_LINES],
+    // cycles the last frame ran over by
+    overrun: u32,
+}
+
+impl Vip {
+    // A VIP that's just been reset, with the monitor ROM in place
+    pub fn new(monitor: &[u8]) -> Result<Vip, String> {
+        if monitor.len() != MONITOR_SIZE {
+            return Err(format!("The monitor ROM should be {} bytes, not {}", MONITOR_SIZE, monitor.len()));
+        }
+        let mut board = Board {
+            ram: [0; RAM_SIZE],
+            monitor: [0; MONITOR_SIZE],
+            monitor_low: true,
+            keys: [false; 16],
+            latched_key: 0,
+            display_on: false,
+            line: 0,
+        };
+        board.monitor.copy_from_slice(monitor);
+        Ok(Vip { cpu: Cdp1802::new(), board, picture: [[0; CHIP8_WIDTH]; PICTURE_LINES], overrun: 0 })
+    }
+
+    // Puts a program in RAM, the CHIP-8 interpreter at 0x0000 and games at
+    // 0x0200, as if it had been loaded from tape
+    pub fn load(&mut self, address: usize, data: &[u8]) -> Result<(), String> {
+        if address + data.len() > RAM_SIZE {
+            return Err(format!("{} bytes at {:#06X} don't fit in the VIP's memory", data.len(), address));
+        }
+        self.board.ram[address..address + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    // A 60th of a second with these keys held on the hex keypad
+    pub fn run_frame(&mut self, keys: [bool; 16]) {
+        self.board.keys = keys;
+        let mut cycle = self.overrun;
+        for line in 0..LINES {
+            self.board.line = line;
+            let picture_line = line.wrapping_sub(FIRST_LINE) as usize;
+            if self.board.display_on && picture_line < PICTURE_LINES {
+                for byte in 0..DMA_BYTES {
+                    let value = self.cpu.dma_out(&mut self.board);
+                    for bit in 0..8 {
+                        self.picture[picture_line][byte * 8 + bit] = (value >> (7 - bit)) & 1;
+                    }
+                }
+                cycle += DMA_BYTES as u32;
+            }
+
+            let line_end = (line + 1) * LINE_CYCLES;
+            let interrupt = self.board.display_on && (INTERRUPT_LINES.0..INTERRUPT_LINES.1).contains(&line);
+            while cycle < line_end {
+                cycle += if interrupt && self.cpu.interrupt() {
+                    1
+                } else if self.cpu.idle {
+                    // nothing happens until the next DMA or interrupt
+                    line_end - cycle
+                } else {
+                    self.cpu.step(&mut self.board)
+                };
+            }
+        }
+        self.overrun = cycle - LINES * LINE_CYCLES;
+        if !self.board.display_on {
+            self.picture = [[0; CHIP8_WIDTH]; PICTURE_LINES];
+        }
+    }
+
+    // The picture as CHIP-8's 32 rows. The CHIP-8 interpreter shows each row
+    // on 4 lines, a pixel is lit if it is on any of them.
+    pub fn screen(&self) -> Screen {
+        let per_row = PICTURE_LINES / CHIP8_HEIGHT;
+        let mut screen = Screen::new(CHIP8_WIDTH, CHIP8_HEIGHT);
+        for y in 0..CHIP8_HEIGHT {
+            for line in &self.picture[y * per_row..(y + 1) * per_row] {
+                for (pixel, &lit) in screen[y].iter_mut().zip(line.iter()) {
+                    *pixel |= lit;
+                }
+            }
+        }
+        screen
+    }
+
+    // Whether Q, which drives the speaker, is on
+    pub fn tone(&self) -> bool {
+        self.cpu.q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vip::{Vip, MONITOR_SIZE};
+
+    // A monitor that gets out of the way: it reaches up to 0x8007 to drop the
+    // ROM from low memory, then runs the program at 0x0000 with P = 0
+    fn vip() -> Vip {
+        let mut monitor = vec![0xF8, 0x80, 0xB2, 0xF8, 0x07, 0xA2, 0xD2, 0xF8, 0x00, 0xA0, 0xB0, 0xD0];
+        monitor.resize(MONITOR_SIZE, 0);
+        Vip::new(&monitor).unwrap()
+    }
+
+    #[test]
+    fn test_display() {
+        let mut vip = vip();
+        // R0 is the DMA pointer, so move the program counter to R3. Then
+        // R1 = 0x0021 (the interrupt routine), R2 = 0x01FF, display on and wait
+        vip.load(0x0000, &[0xF8, 0x00, 0xB3, 0xF8, 0x07, 0xA3, 0xD3,
+                           0xF8, 0x00, 0xB1, 0xF8, 0x21, 0xA1, 0xF8, 0x01, 0xB2, 0xF8, 0xFF, 0xA2,
+                           0xE2, 0x69, 0x30, 0x15]).unwrap();
+        // the interrupt routine points R0 at 0x0300 for the DMA and waits for
+        // the picture to start, so it isn't interrupted again
+        vip.load(0x0020, &[0x70, 0x22, 0x78, 0xF8, 0x03, 0xB0, 0xF8, 0x00, 0xA0, 0x34, 0x29, 0x30, 0x20]).unwrap();
+        let mut picture = [0; 1024];
+        picture[0] = 0x80;
+        picture[5 * 32 + 3 * 8 + 1] = 0x01;
+        vip.load(0x0300, &picture).unwrap();
+
+        vip.run_frame([false; 16]);
+        vip.run_frame([false; 16]);
+        let screen = vip.screen();
+        assert_eq!(screen[0][0], 1);
+        assert_eq!(screen[5][15], 1);
+        assert_eq!(screen.pixels().iter().filter(|&&pixel| pixel != 0).count(), 2);
+    }
+
+    #[test]
+    fn test_keypad() {
+        let mut vip = vip();
+        // latch key 5 with OUT 2, then set Q while EF3 says it's down
+        vip.load(0x0000, &[0xF8, 0x00, 0xB2, 0xF8, 0x20, 0xA2, 0xE2, 0x62, 0x7A, 0x3E, 0x08, 0x7B, 0x30, 0x0C]).unwrap();
+        vip.load(0x0020, &[0x05]).unwrap();
+        vip.run_frame([false; 16]);
+        assert!(!vip.tone());
+        let mut keys = [false; 16];
+        keys[5] = true;
+        vip.run_frame(keys);
+        assert!(vip.tone());
+    }
+}